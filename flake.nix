@@ -39,6 +39,8 @@
 
           cargoExtraArgs = "--offline";
 
+          GIT_HASH = self.shortRev or self.dirtyShortRev or "unknown";
+
           buildInputs = [ ];
         };
 
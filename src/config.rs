@@ -0,0 +1,1045 @@
+use std::{num::NonZeroU64, path::PathBuf, sync::Arc, time::Duration};
+
+use annil::config::MetadataConfig;
+use annil_server::{
+    provider::{
+        AnyProvider, DiscDirFormat, FilterMode, GDriveProvider, LocalFileProvider, OAuthToken,
+        OneDriveProvider, ParallelFetch, PathTemplate, Retry, S3Provider, SeafileCredentials,
+        SeafileProvider, UpstreamLimit, WebdavProvider,
+    },
+    CachePolicy, CorsConfig, RateLimit,
+};
+use axum::http::HeaderValue;
+use reqwest_dav::{re_exports::reqwest, Auth};
+use rusty_s3::{Bucket, Credentials, UrlStyle};
+use serde::Deserialize;
+
+use crate::listen::ListenAddr;
+
+#[derive(Deserialize)]
+pub struct RetryConfig {
+    /// How many times failed upstream requests are retried.
+    #[serde(default = "default_retries")]
+    retries: u32,
+    #[serde(default = "default_retry_delay_ms")]
+    retry_delay_ms: u64,
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_retry_delay_ms() -> u64 {
+    200
+}
+
+#[derive(Deserialize)]
+pub struct LimitConfig {
+    /// Requests sent to the upstream at once, unlimited if unset.
+    max_concurrent_requests: Option<usize>,
+    /// How long requests beyond the limit wait before failing with 503.
+    #[serde(default = "default_queue_wait_ms")]
+    queue_wait_ms: u64,
+}
+
+fn default_queue_wait_ms() -> u64 {
+    5000
+}
+
+/// Proxy for upstream requests, `"none"` connects directly.
+///
+/// Without one, the `HTTP_PROXY` family of environment variables is followed.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ProxyConfig {
+    /// Connect directly, ignoring the environment
+    None,
+    /// Send all requests through the proxy at this url
+    All(reqwest::Proxy),
+}
+
+impl TryFrom<String> for ProxyConfig {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value == "none" {
+            return Ok(Self::None);
+        }
+        reqwest::Proxy::all(&value)
+            .map(Self::All)
+            .map_err(|e| format!("invalid proxy url {value}: {e}"))
+    }
+}
+
+impl LimitConfig {
+    fn limit(&self) -> Option<UpstreamLimit> {
+        self.max_concurrent_requests
+            .map(|max| UpstreamLimit::new(max, Duration::from_millis(self.queue_wait_ms)))
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if self.max_concurrent_requests == Some(0) {
+            errors.push(String::from(
+                "`provider.max_concurrent_requests` must be greater than 0",
+            ));
+        }
+    }
+}
+
+impl RetryConfig {
+    fn retry(&self) -> Retry {
+        Retry {
+            retries: self.retries,
+            base_delay: Duration::from_millis(self.retry_delay_ms),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SeafileConfig {
+    token: String,
+    /// Account to fetch a new token with when `token` expires.
+    username: Option<String>,
+    password: Option<String>,
+    base: String,
+    repo_id: Option<String>,
+    /// Additional repos to serve albums from.
+    ///
+    /// When an album id exists in several repos, `repo_id` takes precedence,
+    /// followed by `repo_ids` in the order given.
+    #[serde(default)]
+    repo_ids: Vec<String>,
+    /// How long resolved download links are reused, 0 disables caching.
+    #[serde(default = "default_link_cache_secs")]
+    link_cache_secs: u64,
+    /// How long directory listings are reused, 0 lists again on every request.
+    ///
+    /// Tracks and covers are found in listings, so this bounds how soon added or removed files
+    /// are noticed between reloads.
+    #[serde(default = "default_listing_cache_secs")]
+    listing_cache_secs: u64,
+    #[serde(flatten)]
+    retry: RetryConfig,
+    #[serde(flatten)]
+    limit: LimitConfig,
+    /// Proxy for requests to this provider, see [`ProxyConfig`].
+    proxy: Option<ProxyConfig>,
+    /// Where tracks are stored, without the extension.
+    #[serde(default)]
+    path_template: PathTemplate,
+    /// Audio file extensions to look for, in order of preference.
+    #[serde(default = "default_extensions")]
+    extensions: Vec<String>,
+    /// Cover file names to look for, in order of preference.
+    #[serde(default = "default_seafile_cover_names")]
+    cover_names: Vec<String>,
+    /// How many ranged requests a proxied track is fetched with at once, 0 or 1 fetches it with
+    /// a single request.
+    #[serde(default)]
+    parallel_fetches: usize,
+    /// Bytes fetched by each of the parallel requests.
+    #[serde(default = "default_chunk_size")]
+    chunk_size: u64,
+    /// Log upstream requests taking longer than this.
+    slow_request_ms: Option<u64>,
+}
+
+/// Well within the lifetime of seafile download links.
+fn default_link_cache_secs() -> u64 {
+    300
+}
+
+fn default_listing_cache_secs() -> u64 {
+    60
+}
+
+fn default_chunk_size() -> u64 {
+    4 * 1024 * 1024
+}
+
+fn default_extensions() -> Vec<String> {
+    ["flac", "m4a", "mp3", "opus"].map(String::from).to_vec()
+}
+
+/// Each extension a track is missing with costs a request, so only FLAC is looked for by default.
+fn default_flac_extension() -> Vec<String> {
+    vec![String::from("flac")]
+}
+
+fn default_cover_names() -> Vec<String> {
+    vec![String::from("cover.jpg")]
+}
+
+fn default_seafile_cover_names() -> Vec<String> {
+    ["cover.jpg", "folder.jpg"].map(String::from).to_vec()
+}
+
+fn validate_extensions(extensions: &[String], errors: &mut Vec<String>) {
+    if extensions.is_empty() {
+        errors.push(String::from("`provider.extensions` must not be empty"));
+    }
+    if let Some(extension) = extensions
+        .iter()
+        .find(|extension| extension.is_empty() || extension.contains(['.', '/']))
+    {
+        errors.push(format!(
+            "`provider.extensions` must be extensions without the dot ({extension})"
+        ));
+    }
+}
+
+fn validate_cover_names(cover_names: &[String], errors: &mut Vec<String>) {
+    if cover_names.is_empty() {
+        errors.push(String::from("`provider.cover_names` must not be empty"));
+    }
+    if let Some(name) = cover_names.iter().find(|name| name.contains('/')) {
+        errors.push(format!(
+            "`provider.cover_names` must be file names, not paths ({name})"
+        ));
+    }
+}
+
+impl SeafileConfig {
+    pub fn build(&self, client: reqwest::Client) -> SeafileProvider {
+        let provider = SeafileProvider::new(
+            client,
+            self.token.clone(),
+            self.base.clone(),
+            self.repo_id.iter().chain(&self.repo_ids).cloned().collect(),
+            Duration::from_secs(self.link_cache_secs),
+            Duration::from_secs(self.listing_cache_secs),
+            self.retry.retry(),
+            Arc::new(self.path_template.clone()),
+            self.extensions.clone(),
+        )
+        .with_cover_names(self.cover_names.clone());
+        let provider = match (&self.username, &self.password) {
+            (Some(username), Some(password)) => provider.with_credentials(SeafileCredentials {
+                username: username.clone(),
+                password: password.clone(),
+            }),
+            _ => provider,
+        };
+        let provider = if self.parallel_fetches > 1 {
+            provider.with_parallel_fetch(ParallelFetch {
+                concurrency: self.parallel_fetches,
+                chunk_size: self.chunk_size,
+            })
+        } else {
+            provider
+        };
+        match self.slow_request_ms {
+            Some(ms) => provider.with_slow_request_threshold(Duration::from_millis(ms)),
+            None => provider,
+        }
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if self.token.trim().is_empty() {
+            errors.push(String::from("`provider.token` must not be blank"));
+        }
+        if let Err(e) = reqwest::Url::parse(&self.base) {
+            errors.push(format!(
+                "`provider.base` is not a valid url ({}): {e}",
+                self.base
+            ));
+        }
+        if self.username.is_some() != self.password.is_some() {
+            errors.push(String::from(
+                "`provider.username` and `provider.password` must be set together",
+            ));
+        }
+        if self.repo_id.is_none() && self.repo_ids.is_empty() {
+            errors.push(String::from(
+                "at least one of `provider.repo_id` and `provider.repo_ids` must be set",
+            ));
+        }
+        validate_extensions(&self.extensions, errors);
+        validate_cover_names(&self.cover_names, errors);
+        if self.chunk_size == 0 {
+            errors.push(String::from("`provider.chunk_size` must be greater than 0"));
+        }
+    }
+}
+
+/// How WebDAV credentials are sent.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebdavAuthMode {
+    #[default]
+    Basic,
+    Digest,
+}
+
+#[derive(Deserialize)]
+pub struct WebdavConfig {
+    host: String,
+    username: Option<String>,
+    #[serde(default)]
+    password: String,
+    /// `basic` or `digest`, ignored without a `username`.
+    #[serde(default)]
+    auth: WebdavAuthMode,
+    /// Where tracks are stored, including the extension unless `extensions` is set.
+    #[serde(default)]
+    path_template: PathTemplate,
+    /// Audio file extensions to look for in the directory of a track, in order of preference.
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(flatten)]
+    retry: RetryConfig,
+    #[serde(flatten)]
+    limit: LimitConfig,
+    /// Proxy for requests to this provider, see [`ProxyConfig`].
+    proxy: Option<ProxyConfig>,
+    /// Cover file names to look for, in order of preference.
+    #[serde(default = "default_cover_names")]
+    cover_names: Vec<String>,
+    /// Redirect clients to links carrying the basic auth credentials, which exposes them to
+    /// every client. Audio is streamed through the server otherwise.
+    #[serde(default)]
+    credentials_in_links: bool,
+}
+
+impl WebdavConfig {
+    pub fn build(&self, client: reqwest::Client) -> WebdavProvider {
+        let auth = match (&self.username, self.auth) {
+            (Some(username), WebdavAuthMode::Basic) => {
+                Auth::Basic(username.clone(), self.password.clone())
+            }
+            (Some(username), WebdavAuthMode::Digest) => {
+                Auth::Digest(username.clone(), self.password.clone())
+            }
+            (None, _) => Auth::Anonymous,
+        };
+        WebdavProvider::new(
+            client,
+            self.host.clone(),
+            auth,
+            Arc::new(self.path_template.clone()),
+            self.retry.retry(),
+        )
+        .with_cover_names(self.cover_names.clone())
+        .with_extensions(self.extensions.clone())
+        .with_credentials_in_links(self.credentials_in_links)
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if let Err(e) = reqwest::Url::parse(&self.host) {
+            errors.push(format!(
+                "`provider.host` is not a valid url ({}): {e}",
+                self.host
+            ));
+        }
+        if matches!(self.auth, WebdavAuthMode::Digest) && self.username.is_none() {
+            errors.push(String::from(
+                "`provider.username` is required with digest auth",
+            ));
+        }
+        if !self.extensions.is_empty() {
+            validate_extensions(&self.extensions, errors);
+        }
+        validate_cover_names(&self.cover_names, errors);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LocalConfig {
+    root: PathBuf,
+    /// Name of disc directories, `{disc}` is replaced with the disc id.
+    #[serde(default)]
+    disc_dir_format: DiscDirFormat,
+    /// Cover file names to look for, in order of preference.
+    #[serde(default = "default_cover_names")]
+    cover_names: Vec<String>,
+    /// Audio file extensions to look for, in order of preference.
+    #[serde(default = "default_extensions")]
+    extensions: Vec<String>,
+}
+
+impl LocalConfig {
+    pub fn build(&self) -> LocalFileProvider {
+        LocalFileProvider::new(self.root.clone())
+            .with_paths(Arc::new(self.disc_dir_format.clone()))
+            .with_cover_names(self.cover_names.clone())
+            .with_extensions(self.extensions.clone())
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if !self.root.is_dir() {
+            errors.push(format!(
+                "`provider.root` is not a directory ({})",
+                self.root.display()
+            ));
+        }
+        validate_extensions(&self.extensions, errors);
+        validate_cover_names(&self.cover_names, errors);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct S3Config {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    /// Use `{endpoint}/{bucket}` urls instead of `{bucket}.{endpoint}`, as MinIO expects.
+    #[serde(default)]
+    path_style: bool,
+    /// How long presigned urls stay valid.
+    #[serde(default = "default_presign_expiry_secs")]
+    presign_expiry_secs: u64,
+    #[serde(flatten)]
+    retry: RetryConfig,
+    #[serde(flatten)]
+    limit: LimitConfig,
+    /// Proxy for requests to this provider, see [`ProxyConfig`].
+    proxy: Option<ProxyConfig>,
+    /// Name of disc directories, `{disc}` is replaced with the disc id.
+    #[serde(default)]
+    disc_dir_format: DiscDirFormat,
+    /// Audio file extensions to look for, in order of preference.
+    #[serde(default = "default_flac_extension")]
+    extensions: Vec<String>,
+}
+
+fn default_presign_expiry_secs() -> u64 {
+    3600
+}
+
+impl S3Config {
+    pub fn build(&self, client: reqwest::Client) -> Result<S3Provider, Box<dyn std::error::Error>> {
+        let style = if self.path_style {
+            UrlStyle::Path
+        } else {
+            UrlStyle::VirtualHost
+        };
+        let bucket = Bucket::new(
+            self.endpoint.parse()?,
+            style,
+            self.bucket.clone(),
+            self.region.clone(),
+        )?;
+        Ok(S3Provider::new(
+            client,
+            bucket,
+            Credentials::new(self.access_key.clone(), self.secret_key.clone()),
+            Duration::from_secs(self.presign_expiry_secs),
+            self.retry.retry(),
+            Arc::new(self.disc_dir_format.clone()),
+        )
+        .with_extensions(self.extensions.clone()))
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if let Err(e) = reqwest::Url::parse(&self.endpoint) {
+            errors.push(format!(
+                "`provider.endpoint` is not a valid url ({}): {e}",
+                self.endpoint
+            ));
+        }
+        if self.presign_expiry_secs == 0 {
+            errors.push(String::from(
+                "`provider.presign_expiry_secs` must be greater than 0",
+            ));
+        }
+        validate_extensions(&self.extensions, errors);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GDriveConfig {
+    folder_id: String,
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+    #[serde(flatten)]
+    retry: RetryConfig,
+    #[serde(flatten)]
+    limit: LimitConfig,
+    /// Proxy for requests to this provider, see [`ProxyConfig`].
+    proxy: Option<ProxyConfig>,
+    /// Name of disc directories, `{disc}` is replaced with the disc id.
+    #[serde(default)]
+    disc_dir_format: DiscDirFormat,
+    /// Audio file extensions to look for, in order of preference.
+    #[serde(default = "default_flac_extension")]
+    extensions: Vec<String>,
+}
+
+impl GDriveConfig {
+    pub fn build(&self, client: reqwest::Client) -> GDriveProvider {
+        let token = OAuthToken::google(
+            client.clone(),
+            self.client_id.clone(),
+            self.client_secret.clone(),
+            self.refresh_token.clone(),
+        );
+        GDriveProvider::new(
+            client,
+            token,
+            self.folder_id.clone(),
+            self.retry.retry(),
+            Arc::new(self.disc_dir_format.clone()),
+        )
+        .with_extensions(self.extensions.clone())
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        validate_extensions(&self.extensions, errors);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OneDriveConfig {
+    /// Folder holding albums, relative to the drive root.
+    #[serde(default)]
+    folder: String,
+    #[serde(default = "default_tenant")]
+    tenant: String,
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+    #[serde(flatten)]
+    retry: RetryConfig,
+    #[serde(flatten)]
+    limit: LimitConfig,
+    /// Proxy for requests to this provider, see [`ProxyConfig`].
+    proxy: Option<ProxyConfig>,
+    /// Name of disc directories, `{disc}` is replaced with the disc id.
+    #[serde(default)]
+    disc_dir_format: DiscDirFormat,
+    /// Audio file extensions to look for, in order of preference.
+    #[serde(default = "default_flac_extension")]
+    extensions: Vec<String>,
+}
+
+fn default_tenant() -> String {
+    String::from("common")
+}
+
+impl OneDriveConfig {
+    pub fn build(&self, client: reqwest::Client) -> OneDriveProvider {
+        let token = OAuthToken::microsoft(
+            client.clone(),
+            &self.tenant,
+            self.client_id.clone(),
+            self.client_secret.clone(),
+            self.refresh_token.clone(),
+        );
+        OneDriveProvider::new(
+            client,
+            token,
+            &self.folder,
+            self.retry.retry(),
+            Arc::new(self.disc_dir_format.clone()),
+        )
+        .with_extensions(self.extensions.clone())
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        validate_extensions(&self.extensions, errors);
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProviderConfig {
+    Seafile(SeafileConfig),
+    Webdav(WebdavConfig),
+    Local(LocalConfig),
+    S3(S3Config),
+    #[serde(rename = "gdrive")]
+    GDrive(GDriveConfig),
+    #[serde(rename = "onedrive")]
+    OneDrive(OneDriveConfig),
+}
+
+impl ProviderConfig {
+    pub fn build(
+        &self,
+        client: reqwest::Client,
+        probe_duration: bool,
+    ) -> Result<AnyProvider, Box<dyn std::error::Error>> {
+        Ok(match self {
+            Self::Seafile(config) => {
+                AnyProvider::Seafile(config.build(client).with_probe_duration(probe_duration))
+            }
+            Self::Webdav(config) => {
+                AnyProvider::Webdav(config.build(client).with_probe_duration(probe_duration))
+            }
+            Self::Local(config) => {
+                AnyProvider::Local(config.build().with_probe_duration(probe_duration))
+            }
+            Self::S3(config) => {
+                AnyProvider::S3(config.build(client)?.with_probe_duration(probe_duration))
+            }
+            Self::GDrive(config) => {
+                AnyProvider::GDrive(config.build(client).with_probe_duration(probe_duration))
+            }
+            Self::OneDrive(config) => {
+                AnyProvider::OneDrive(config.build(client).with_probe_duration(probe_duration))
+            }
+        })
+    }
+
+    fn secrets_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Self::Seafile(config) => [&mut config.token]
+                .into_iter()
+                .chain(config.password.as_mut())
+                .collect(),
+            Self::Webdav(config) => vec![&mut config.password],
+            Self::Local(_) => Vec::new(),
+            Self::S3(config) => vec![&mut config.access_key, &mut config.secret_key],
+            Self::GDrive(config) => vec![&mut config.client_secret, &mut config.refresh_token],
+            Self::OneDrive(config) => vec![&mut config.client_secret, &mut config.refresh_token],
+        }
+    }
+
+    /// Limit of concurrent upstream requests, local files have none.
+    pub fn upstream_limit(&self) -> Option<UpstreamLimit> {
+        self.limit_config().and_then(LimitConfig::limit)
+    }
+
+    /// Proxy to reach the provider through, local files need none.
+    pub fn proxy(&self) -> Option<&ProxyConfig> {
+        match self {
+            Self::Seafile(config) => config.proxy.as_ref(),
+            Self::Webdav(config) => config.proxy.as_ref(),
+            Self::Local(_) => None,
+            Self::S3(config) => config.proxy.as_ref(),
+            Self::GDrive(config) => config.proxy.as_ref(),
+            Self::OneDrive(config) => config.proxy.as_ref(),
+        }
+    }
+
+    fn limit_config(&self) -> Option<&LimitConfig> {
+        match self {
+            Self::Seafile(config) => Some(&config.limit),
+            Self::Webdav(config) => Some(&config.limit),
+            Self::Local(_) => None,
+            Self::S3(config) => Some(&config.limit),
+            Self::GDrive(config) => Some(&config.limit),
+            Self::OneDrive(config) => Some(&config.limit),
+        }
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        match self {
+            Self::Seafile(config) => config.validate(errors),
+            Self::Webdav(config) => config.validate(errors),
+            Self::Local(config) => config.validate(errors),
+            Self::S3(config) => config.validate(errors),
+            Self::GDrive(config) => config.validate(errors),
+            Self::OneDrive(config) => config.validate(errors),
+        }
+        if let Some(limit) = self.limit_config() {
+            limit.validate(errors);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain.
+    pub cert_path: PathBuf,
+    /// PEM encoded private key.
+    pub key_path: PathBuf,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// One address or a list of them, such as `["0.0.0.0:3614", "[::]:3614"]`.
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen: Vec<ListenAddr>,
+    /// Serve admin routes on a separate address instead of `listen`.
+    pub admin_listen: Option<ListenAddr>,
+    /// Permissions of unix sockets, such as `0o660`.
+    pub socket_mode: Option<u32>,
+    /// Serve https on tcp addresses if set.
+    pub tls: Option<TlsConfig>,
+    /// Limit requests per client ip, admin routes are exempt.
+    pub rate_limit: Option<RateLimit>,
+    pub sign_key: String,
+    pub share_key: String,
+    pub admin_token: String,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    /// Serve only some of the albums of the provider.
+    pub filter: Option<FilterConfig>,
+    /// Manifest of album and track titles, read again on every reload.
+    pub manifest: Option<PathBuf>,
+    /// Anni metadata repository handed to annil.
+    pub metadata: Option<MetadataSource>,
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// `User-Agent` of upstream requests.
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// Reload the provider periodically if set.
+    pub reload_interval_secs: Option<u64>,
+    /// Give up a reload if listing the albums its etag is computed from takes longer than this.
+    #[serde(default = "default_reload_etag_timeout_secs")]
+    pub reload_etag_timeout_secs: u64,
+    /// Idle connections kept open to each upstream host.
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+    #[serde(default = "default_tcp_keepalive_secs")]
+    pub tcp_keepalive_secs: u64,
+    /// How long in-flight requests may take to finish on shutdown.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    /// `Cache-Control` of audio and cover responses.
+    #[serde(default)]
+    pub cache_control: CachePolicy,
+    /// Serve prometheus metrics on `/metrics`.
+    #[serde(default)]
+    pub metrics: bool,
+    /// Fetch audio on the server and stream it, instead of redirecting clients to upstream
+    /// links they may not be able to reach.
+    #[serde(default)]
+    pub proxy_when_private: bool,
+    /// Read the duration of FLAC audio from its header while streaming it, turn off to skip the
+    /// work when clients read durations from their own metadata.
+    #[serde(default = "default_probe_duration")]
+    pub probe_duration: bool,
+    /// Refuse audio files larger than this, checked before streaming or redirecting.
+    pub max_audio_bytes: Option<u64>,
+    /// Stream at most this many bytes of audio per request, longer ranges are answered in part.
+    /// Requests without a range are refused for larger files.
+    ///
+    /// Only applies to audio streamed through the server, not to redirects.
+    pub max_bytes_per_request: Option<NonZeroU64>,
+    /// Path to serve all routes under, such as `/music` behind a reverse proxy shared with other
+    /// apps.
+    pub base_path: Option<String>,
+    /// How long the album list is served from memory, 0 lists albums on every request.
+    #[serde(default)]
+    pub album_cache_secs: u64,
+    /// Memory for caching covers in bytes, 0 disables the cache.
+    #[serde(default)]
+    pub cover_cache_bytes: usize,
+    /// Covers converted to WebP for clients accepting it, kept in memory, 0 serves covers as
+    /// they are.
+    ///
+    /// Only covers the provider serves as files are converted, linked covers are not fetched.
+    #[serde(default)]
+    pub webp_cover_entries: usize,
+    /// How long cached cover links are reused.
+    #[serde(default = "default_cover_cache_link_secs")]
+    pub cover_cache_link_secs: u64,
+    /// Resolve the covers of all albums in the background at startup and after reloads.
+    ///
+    /// Needs `cover_cache_bytes` to keep the resolved covers.
+    #[serde(default)]
+    pub prewarm_covers: bool,
+    /// Delay between covers resolved when prewarming.
+    #[serde(default = "default_prewarm_interval_ms")]
+    pub prewarm_interval_ms: u64,
+
+    pub provider: ProviderConfig,
+    /// Served from when `provider` fails, such as a mirror of the library.
+    ///
+    /// `max_concurrent_requests` is only applied to `provider`.
+    pub fallback: Option<ProviderConfig>,
+}
+
+#[derive(Deserialize)]
+pub struct AdminConfig {
+    /// Serve `/admin/sign`, turn off if tokens are handed out by other means.
+    #[serde(default = "default_enable_sign")]
+    pub enable_sign: bool,
+    /// Serve `/stats` without the admin token.
+    #[serde(default)]
+    pub public_stats: bool,
+    /// Serve covers without a user or share token, for libraries whose albums are public.
+    #[serde(default)]
+    pub public_covers: bool,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enable_sign: default_enable_sign(),
+            public_stats: false,
+            public_covers: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FilterConfig {
+    pub mode: FilterMode,
+    /// File listing album ids, one per line, re-read on reload.
+    pub path: PathBuf,
+}
+
+fn default_enable_sign() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct MetadataSource {
+    /// Git url of the repository, or a local directory holding a clone in `repo`, which is
+    /// loaded without being pulled.
+    pub source: String,
+    #[serde(default = "default_metadata_branch")]
+    pub branch: String,
+    /// Where a repository given by url is cloned to.
+    #[serde(default = "default_metadata_base")]
+    pub base: PathBuf,
+}
+
+fn default_metadata_branch() -> String {
+    String::from("master")
+}
+
+fn default_metadata_base() -> PathBuf {
+    PathBuf::from("metadata")
+}
+
+impl MetadataSource {
+    pub fn build(&self) -> MetadataConfig {
+        if self.is_url() {
+            MetadataConfig {
+                repo: self.source.clone(),
+                branch: self.branch.clone(),
+                base: self.base.clone(),
+                pull: true,
+            }
+        } else {
+            MetadataConfig {
+                repo: String::new(),
+                branch: self.branch.clone(),
+                base: PathBuf::from(&self.source),
+                pull: false,
+            }
+        }
+    }
+
+    fn is_url(&self) -> bool {
+        // windows paths such as `C:\metadata` parse as urls with a one letter scheme
+        reqwest::Url::parse(&self.source).is_ok_and(|url| url.scheme().len() > 1)
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        if !self.is_url() && !std::path::Path::new(&self.source).join("repo").is_dir() {
+            errors.push(format!(
+                "`metadata.source` is neither a url nor a directory holding a clone in `repo` ({})",
+                self.source
+            ));
+        }
+    }
+}
+
+/// Accepts a single value where a list is expected.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+fn default_user_agent() -> String {
+    String::from(concat!("AnnilServer/", env!("CARGO_PKG_VERSION")))
+}
+
+fn default_probe_duration() -> bool {
+    true
+}
+
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_reload_etag_timeout_secs() -> u64 {
+    60
+}
+
+fn default_pool_max_idle_per_host() -> usize {
+    32
+}
+
+fn default_tcp_keepalive_secs() -> u64 {
+    60
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+fn default_cover_cache_link_secs() -> u64 {
+    300
+}
+
+fn default_prewarm_interval_ms() -> u64 {
+    100
+}
+
+/// Replaces a `${VAR}` value with the content of environment variable `VAR`.
+///
+/// Other values are left untouched.
+fn resolve_env(value: &mut String) -> Result<(), String> {
+    if let Some(var) = value.strip_prefix("${").and_then(|v| v.strip_suffix('}')) {
+        *value = std::env::var(var)
+            .map_err(|_| format!("environment variable `{var}` is not set"))?;
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Resolves secrets given as `${VAR}` against the environment.
+    pub fn resolve_secrets(&mut self) -> Result<(), Vec<String>> {
+        let errors: Vec<_> = [
+            &mut self.sign_key,
+            &mut self.share_key,
+            &mut self.admin_token,
+        ]
+        .into_iter()
+        .chain(self.provider.secrets_mut())
+        .chain(
+            self.fallback
+                .iter_mut()
+                .flat_map(ProviderConfig::secrets_mut),
+        )
+        .filter_map(|value| resolve_env(value).err())
+        .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks the config for mistakes serde can't catch, reporting all of them at once.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.sign_key.is_empty() {
+            errors.push(String::from("`sign_key` must not be empty"));
+        }
+        if self.share_key.is_empty() {
+            errors.push(String::from("`share_key` must not be empty"));
+        }
+        if self.admin_token.trim().is_empty() {
+            errors.push(String::from("`admin_token` must not be blank"));
+        }
+        if self.prewarm_interval_ms == 0 {
+            errors.push(String::from("`prewarm_interval_ms` must be greater than 0"));
+        }
+        if self.prewarm_covers && self.cover_cache_bytes == 0 {
+            errors.push(String::from(
+                "`prewarm_covers` needs `cover_cache_bytes` to be greater than 0",
+            ));
+        }
+        if self.listen.is_empty() {
+            errors.push(String::from("`listen` must not be empty"));
+        }
+        if self.request_timeout_secs == 0 {
+            errors.push(String::from("`request_timeout_secs` must be greater than 0"));
+        }
+        if self.reload_interval_secs == Some(0) {
+            errors.push(String::from(
+                "`reload_interval_secs` must be greater than 0",
+            ));
+        }
+        if self.reload_etag_timeout_secs == 0 {
+            errors.push(String::from(
+                "`reload_etag_timeout_secs` must be greater than 0",
+            ));
+        }
+        if self.max_audio_bytes == Some(0) {
+            errors.push(String::from("`max_audio_bytes` must be greater than 0"));
+        }
+        #[cfg(not(feature = "webp-covers"))]
+        if self.webp_cover_entries > 0 {
+            errors.push(String::from(
+                "`webp_cover_entries` needs the server to be built with the `webp-covers` feature",
+            ));
+        }
+        if let Some(path) = &self.base_path {
+            if !path.starts_with('/') || path.ends_with('/') {
+                errors.push(format!(
+                    "`base_path` must start with a slash and not end with one ({path})"
+                ));
+            }
+            if path.contains([':', '*']) {
+                errors.push(format!(
+                    "`base_path` must not contain path parameters ({path})"
+                ));
+            }
+        }
+
+        #[cfg(unix)]
+        if self.tls.is_some()
+            && self
+                .listen
+                .iter()
+                .chain(&self.admin_listen)
+                .any(|addr| matches!(addr, ListenAddr::Unix(_)))
+        {
+            errors.push(String::from("`tls` can't be used with unix sockets"));
+        }
+
+        if HeaderValue::from_str(&self.user_agent).is_err() {
+            errors.push(String::from("`user_agent` is not a valid header value"));
+        }
+        for (name, value) in [
+            ("audio", &self.cache_control.audio),
+            ("cover", &self.cache_control.cover),
+            ("cover_link", &self.cache_control.cover_link),
+        ] {
+            if HeaderValue::from_str(value).is_err() {
+                errors.push(format!(
+                    "`cache_control.{name}` is not a valid header value"
+                ));
+            }
+        }
+
+        self.cors.public.validate("cors", &mut errors);
+        if let Some(admin) = &self.cors.admin {
+            admin.validate("cors.admin", &mut errors);
+        }
+
+        if let Some(limit) = &self.rate_limit {
+            if limit.requests_per_second.is_nan() || limit.requests_per_second <= 0.0 {
+                errors.push(String::from(
+                    "`rate_limit.requests_per_second` must be greater than 0",
+                ));
+            }
+            if limit.burst == 0 {
+                errors.push(String::from("`rate_limit.burst` must be greater than 0"));
+            }
+        }
+
+        if let Some(metadata) = &self.metadata {
+            metadata.validate(&mut errors);
+        }
+        self.provider.validate(&mut errors);
+        if let Some(fallback) = &self.fallback {
+            fallback.validate(&mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
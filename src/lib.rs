@@ -1,32 +1,68 @@
+#[cfg(feature = "test-util")]
+pub mod mock;
 pub mod provider;
 
 use std::{
-    num::NonZeroU8,
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    borrow::Cow,
+    collections::HashSet,
+    io::Cursor,
+    net::{IpAddr, SocketAddr},
+    num::{NonZeroU64, NonZeroU8, NonZeroUsize},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use anni_provider::{AnniProvider, ProviderError, Range};
+use anni_provider::{
+    AnniProvider, AudioInfo, AudioResourceReader, ProviderError, Range, ResourceReader,
+};
+use anni_repo::RepositoryManager;
 use annil::{
-    extractor::track::TrackIdentifier,
+    config::MetadataConfig,
+    extractor::token::AnnilClaim,
     provider::AnnilProvider,
     state::{AnnilKeys, AnnilState},
 };
 use axum::{
-    extract::Path,
+    async_trait,
+    body::{Body, HttpBody},
+    extract::{ConnectInfo, FromRequestParts, MatchedPath, Path, Query, Request, State},
+    handler::Handler,
     http::{
-        header::{ACCESS_CONTROL_EXPOSE_HEADERS, CACHE_CONTROL},
-        Method, StatusCode,
+        header::{
+            ACCEPT, ACCEPT_RANGES, ACCESS_CONTROL_EXPOSE_HEADERS, AUTHORIZATION, CACHE_CONTROL,
+            CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+            IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE, RETRY_AFTER, VARY,
+        },
+        request::Parts,
+        HeaderMap, HeaderValue, Method, StatusCode, Uri,
     },
-    response::{IntoResponse, Redirect, Response},
+    middleware::{self, Next},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
     routing::{get, post},
-    Extension, Router,
+    Extension, Json, RequestExt, Router,
+};
+use lru::LruCache;
+use metrics::Label;
+use metrics_exporter_prometheus::PrometheusHandle;
+use provider::{
+    audio_quality, limit_reader, read_tags, AlbumTitles, AnniURLProvider, AudioDetails, DiscTracks,
+    RangeNotSatisfiable, StreamInfo, StreamedAudio, UpstreamBusy, Validators, MAX_METADATA_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::{
+    io::AsyncReadExt,
+    sync::{Notify, RwLock},
+    task::JoinHandle,
+    time::MissedTickBehavior,
 };
-use provider::{AnniURLProvider, SeafileProvider};
-use serde::Deserialize;
-use tokio::sync::RwLock;
+use tokio_util::io::ReaderStream;
 use tower::ServiceBuilder;
-use tower_http::cors;
+use tower_http::{compression::CompressionLayer, cors};
+use tracing::Instrument;
 
 #[derive(Deserialize)]
 struct CoverPath {
@@ -34,137 +70,2243 @@ struct CoverPath {
     disc_id: Option<NonZeroU8>,
 }
 
+#[derive(Deserialize)]
+struct CoverQuery {
+    /// longest side of the thumbnail to serve instead of the full cover
+    size: Option<u32>,
+}
+
+const MAX_THUMBNAIL_SIZE: u32 = 4096;
+
 #[derive(Debug)]
 enum Error {
-    AnniError(ProviderError),
+    /// The requested resource does not exist upstream
+    NotFound(ProviderError),
+    /// The upstream failed to serve the request
+    Upstream(ProviderError),
+    Timeout,
+    /// Too many requests to the upstream are in flight
+    Busy,
+    /// The request itself is malformed
+    BadRequest(String),
+    /// The provider can't serve this kind of request
+    NotImplemented(&'static str),
+    /// The request lacks a valid token of this kind
+    Unauthorized(&'static str),
+    /// The client has to wait for this many seconds
+    RateLimited(u64),
+    /// The requested range starts beyond the end of a file of this size
+    RangeNotSatisfiable(u64),
+    /// The file is larger than audio is allowed to be
+    TooLarge {
+        size: u64,
+        limit: u64,
+    },
+    /// A reload holds the provider
+    Reloading,
 }
 
 impl From<ProviderError> for Error {
     fn from(error: ProviderError) -> Self {
-        Self::AnniError(error)
+        match error {
+            ProviderError::FileNotFound | ProviderError::InvalidPath => Self::NotFound(error),
+            ProviderError::RequestError(ref e) if e.is_timeout() => Self::Timeout,
+            ProviderError::IOError(ref e)
+                if e.get_ref().is_some_and(|e| e.is::<UpstreamBusy>()) =>
+            {
+                Self::Busy
+            }
+            ProviderError::IOError(ref e)
+                if e.get_ref().is_some_and(|e| e.is::<RangeNotSatisfiable>()) =>
+            {
+                let size = e
+                    .get_ref()
+                    .and_then(|e| e.downcast_ref::<RangeNotSatisfiable>())
+                    .map_or(0, |e| e.size);
+                Self::RangeNotSatisfiable(size)
+            }
+            ProviderError::RequestError(ref e) if e.status() == Some(StatusCode::NOT_FOUND) => {
+                Self::NotFound(error)
+            }
+            error => Self::Upstream(error),
+        }
     }
 }
 
+/// Body of error responses.
+#[derive(Serialize)]
+struct ErrorBody {
+    /// Stable code for clients to match on, unlike the message.
+    error: &'static str,
+    message: String,
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        match self {
-            Self::AnniError(error) => (
-                StatusCode::NOT_FOUND,
-                [(CACHE_CONTROL, "private")],
-                error.to_string(),
+        let (status, error, message) = match &self {
+            Self::NotFound(error) => (StatusCode::NOT_FOUND, "not_found", error.to_string()),
+            Self::Upstream(error) => (StatusCode::BAD_GATEWAY, "upstream", error.to_string()),
+            Self::Timeout => (
+                StatusCode::GATEWAY_TIMEOUT,
+                "upstream_timeout",
+                String::from("upstream request timed out"),
+            ),
+            Self::Busy => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "upstream_busy",
+                String::from("too many concurrent upstream requests"),
+            ),
+            Self::BadRequest(message) => (StatusCode::BAD_REQUEST, "bad_request", message.clone()),
+            Self::NotImplemented(message) => (
+                StatusCode::NOT_IMPLEMENTED,
+                "not_implemented",
+                String::from(*message),
             ),
+            Self::Unauthorized(kind) => (
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                format!("missing or invalid {kind} token"),
+            ),
+            Self::RateLimited(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                String::from("too many requests"),
+            ),
+            Self::RangeNotSatisfiable(size) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "range_not_satisfiable",
+                format!("range starts beyond the end of the file of {size} bytes"),
+            ),
+            Self::Reloading => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "reloading",
+                String::from("the library is being reloaded"),
+            ),
+            Self::TooLarge { size, limit } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "too_large",
+                format!("file of {size} bytes exceeds the limit of {limit} bytes"),
+            ),
+        };
+
+        let mut response = (
+            status,
+            [(CACHE_CONTROL, "private")],
+            Json(ErrorBody { error, message }),
+        )
+            .into_response();
+        match self {
+            Self::RateLimited(retry_after) => {
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, retry_after.into());
+            }
+            Self::Busy | Self::Reloading => {
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+            }
+            Self::RangeNotSatisfiable(size) => {
+                let content_range = format!("bytes */{size}").parse().unwrap();
+                response.headers_mut().insert(CONTENT_RANGE, content_range);
+            }
+            _ => {}
         }
-        .into_response()
+        response
     }
 }
 
+/// Path of a track, rejected with an explanation if the disc or track id is out of range, or
+/// if the request has no token that may fetch the track.
+struct TrackPath {
+    album_id: String,
+    disc_id: NonZeroU8,
+    track_id: NonZeroU8,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TrackPath {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path((album_id, disc_id, track_id)) =
+            Path::<(String, String, String)>::from_request_parts(parts, state)
+                .await
+                .map_err(|e| Error::BadRequest(e.body_text()))?;
+        let track = Self {
+            album_id: sanitize_album_id(&album_id)?.to_owned(),
+            disc_id: parse_index("disc", &disc_id)?,
+            track_id: parse_index("track", &track_id)?,
+        };
+        // share tokens only cover the tracks they were issued for
+        let claim = AnnilClaim::from_request_parts(parts, state)
+            .await
+            .map_err(|_| Error::Unauthorized("user or share"))?;
+        if !claim.can_fetch(&track.album_id, Some(track.disc_id), Some(track.track_id)) {
+            return Err(Error::Unauthorized("user or share"));
+        }
+        Ok(track)
+    }
+}
+
+/// Checks that an album id can be put into a provider path as a single segment.
+///
+/// Trailing slashes are dropped, ids containing path separators, `..` or control characters
+/// are rejected.
+fn sanitize_album_id(album_id: &str) -> Result<&str, Error> {
+    let album_id = album_id.trim_end_matches('/');
+    let invalid = album_id.is_empty()
+        || album_id == "."
+        || album_id == ".."
+        || album_id.contains(['/', '\\'])
+        || album_id.chars().any(char::is_control);
+    if invalid {
+        Err(Error::BadRequest(format!(
+            "invalid album id `{}`",
+            album_id.escape_debug()
+        )))
+    } else {
+        Ok(album_id)
+    }
+}
+
+fn parse_index(kind: &str, id: &str) -> Result<NonZeroU8, Error> {
+    id.parse().map_err(|_| {
+        Error::BadRequest(format!(
+            "{kind} id must be an integer between 1 and 255, got `{id}`"
+        ))
+    })
+}
+
+#[derive(Deserialize)]
+struct AudioQuery {
+    /// `?download=1` asks for the track as an attachment rather than for playback
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    download: bool,
+}
+
+/// Accepts `1`/`0` besides `true`/`false`, as query flags are usually written.
+fn deserialize_flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        other => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(other),
+            &"1, 0, true or false",
+        )),
+    }
+}
+
+/// Redirects to the audio of a track, or streams it if the provider has no link or audio is
+/// proxied.
+///
+/// `?download=1` only takes effect when the audio is streamed by this server, as the headers of
+/// redirect targets are up to the upstream.
+#[tracing::instrument(skip_all, fields(
+    album_id = %track.album_id,
+    disc_id = track.disc_id.get(),
+    track_id = track.track_id.get(),
+))]
 async fn audio_redirect<P: AnniURLProvider + Send>(
-    track: TrackIdentifier,
+    track: TrackPath,
+    Query(query): Query<AudioQuery>,
+    headers: HeaderMap,
     Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+    Extension(cache): Extension<Arc<CachePolicy>>,
+    Extension(ProxyAudio(proxy)): Extension<ProxyAudio>,
+    Extension(max_bytes): Extension<MaxAudioBytes>,
+    Extension(per_request): Extension<MaxBytesPerRequest>,
 ) -> Response {
     let provider = provider.read().await;
 
-    let uri = match provider
-        .get_audio_link(
-            &track.album_id.to_string(),
-            track.disc_id,
-            track.track_id,
-            Range::FULL,
-        )
-        .await
-    {
-        Ok(Ok(uri)) => uri,
-        Err(e) => return Error::from(dbg!(e)).into_response(),
-        _ => return (StatusCode::NOT_FOUND, [(CACHE_CONTROL, "private")]).into_response(),
+    let requested = request_range(&headers);
+    // only clients that asked for a range can be answered with part of the file
+    let ranged = !matches!(requested, Ok(None));
+    let link = if proxy {
+        let range = match requested {
+            Ok(range) => range,
+            Err(len) => match suffix_range(&*provider, &track, len).await {
+                Ok(range) => Some(range),
+                Err(e) => return e.into_response(),
+            },
+        };
+        let range = range.map_or(Range::FULL, |range| per_request.clamp(range));
+        provider
+            .get_audio_stream(&track.album_id, track.disc_id, track.track_id, range)
+            .await
+            .map(Err)
+    } else {
+        // clients resend their range to links, so a suffix is only resolved if the provider
+        // streams the audio after all, from the size of the first byte it streamed, and the
+        // per request limit is applied to streamed audio below
+        let range = match requested {
+            Ok(range) => range.unwrap_or(Range::FULL),
+            Err(_) => FIRST_BYTE,
+        };
+        // links aren't always checked, reading the details finds out about missing tracks
+        let link = provider
+            .get_audio_link_details(&track.album_id, track.disc_id, track.track_id, range)
+            .await;
+        match (link, requested) {
+            (Ok(Err(StreamedAudio { audio, .. })), Err(len)) => {
+                let size = audio.range.total.unwrap_or(audio.info.size as u64);
+                let range = match suffix_of(size, len) {
+                    Ok(range) => per_request.clamp(range),
+                    Err(e) => return e.into_response(),
+                };
+                provider
+                    .get_audio_stream(&track.album_id, track.disc_id, track.track_id, range)
+                    .await
+                    .map(Err)
+            }
+            (link, _) => link,
+        }
     };
+    let (uri, details) = match link {
+        Ok(Ok(linked)) => linked,
+        Ok(Err(StreamedAudio { mut audio, stream })) => {
+            // only part of the file may have been fetched, the total is the size of all of it
+            let size = audio.range.total.unwrap_or(audio.info.size as u64);
+            if let Err(e) = max_bytes.check(size) {
+                return e.into_response();
+            }
+            if let MaxBytesPerRequest(Some(max)) = per_request {
+                let max = max.get();
+                if !ranged && size > max {
+                    tracing::warn!(
+                        size,
+                        max,
+                        "refusing to serve audio beyond the per request limit"
+                    );
+                    return Error::TooLarge { size, limit: max }.into_response();
+                }
+                // upstreams may ignore the range, so the cut is made here as well
+                audio.reader = limit_reader(audio.reader, max);
+                audio.range = per_request.clamp(audio.range);
+            }
+            let filename = query
+                .download
+                .then(|| download_filename(&track, &audio.info));
+            return stream_audio(StreamedAudio { audio, stream }, filename, &cache.audio);
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to resolve audio link");
+            return Error::from(e).into_response();
+        }
+    };
+
+    if let Err(e) = max_bytes.check(details.info.size as u64) {
+        return e.into_response();
+    }
+    let header = [
+        (ACCESS_CONTROL_EXPOSE_HEADERS, AUDIO_EXPOSE_HEADERS),
+        // links point at files served with range support, clients seek by resending `Range`
+        (ACCEPT_RANGES, "bytes"),
+        (CACHE_CONTROL, cache.audio.as_str()),
+    ];
 
-    let info = match provider
-        .get_audio_info(&track.album_id.to_string(), track.disc_id, track.track_id)
+    (
+        header,
+        AppendHeaders(details_headers(details)),
+        Redirect::temporary(&uri),
+    )
+        .into_response()
+}
+
+/// Answers with the headers GET sends along with audio, reading only the header of the track.
+///
+/// This replaces annil's handler, which leaves out the stream info that GET reports for linked
+/// audio.
+#[tracing::instrument(skip_all, fields(
+    album_id = %track.album_id,
+    disc_id = track.disc_id.get(),
+    track_id = track.track_id.get(),
+))]
+async fn audio_head<P: AnniURLProvider + Send + Sync>(
+    track: TrackPath,
+    Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+    Extension(cache): Extension<Arc<CachePolicy>>,
+) -> Response {
+    let provider = provider.read().await;
+
+    let details = match provider
+        .get_audio_details(&track.album_id, track.disc_id, track.track_id)
         .await
     {
-        Ok(info) => info,
-        Err(e) => return Error::from(dbg!(e)).into_response(),
-    };
-    let header = [(
-        ACCESS_CONTROL_EXPOSE_HEADERS,
-        "X-Origin-Type, X-Origin-Size, X-Duration-Seconds, X-Audio-Quality".to_string(),
-    )];
-    let headers = [
-        ("X-Origin-Type", format!("audio/{}", info.extension)),
+        Ok(details) => details,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to read audio info");
+            return Error::from(e).into_response();
+        }
+    };
+    let header = [
+        (ACCESS_CONTROL_EXPOSE_HEADERS, AUDIO_EXPOSE_HEADERS),
+        (ACCEPT_RANGES, "bytes"),
+        (CACHE_CONTROL, cache.audio.as_str()),
+        (CONTENT_TYPE, audio_mime_type(&details.info.extension)),
+    ];
+    let length = [(CONTENT_LENGTH, details.info.size.to_string())];
+
+    (header, length, AppendHeaders(details_headers(details))).into_response()
+}
+
+/// Headers describing a track, as sent for linked audio.
+fn details_headers(details: AudioDetails) -> Vec<(&'static str, String)> {
+    let AudioDetails { info, stream } = details;
+    let mut headers = origin_headers(&info, stream.as_ref());
+    if let Some(stream) = stream {
+        headers.extend([
+            ("X-Duration-Millis", format!("{}", stream.duration_millis)),
+            ("X-Sample-Rate", format!("{}", stream.sample_rate)),
+            ("X-Bit-Depth", format!("{}", stream.bits_per_sample)),
+            ("X-Channels", format!("{}", stream.channels)),
+        ]);
+    }
+    headers
+}
+
+/// Whether audio is streamed through this server even if the provider can link to it, for
+/// upstreams that only the server can reach.
+#[derive(Clone, Copy)]
+struct ProxyAudio(bool);
+
+/// Largest audio file served, so that a stray file in an album, such as a video, isn't
+/// transferred by accident.
+#[derive(Clone, Copy)]
+struct MaxAudioBytes(Option<u64>);
+
+/// Most bytes of audio streamed in response to one request, so that no client ties up the
+/// upstream with long transfers.
+///
+/// Longer ranges are cut short and answered as partial content, clients fetch the rest with
+/// further range requests. Requests without a range can't be answered in part, and are refused
+/// for files over the limit.
+#[derive(Clone, Copy)]
+struct MaxBytesPerRequest(Option<NonZeroU64>);
+
+impl MaxBytesPerRequest {
+    fn clamp(self, range: Range) -> Range {
+        let Some(max) = self.0 else {
+            return range;
+        };
+        let last = range.start.saturating_add(max.get() - 1);
+        Range {
+            end: Some(range.end.map_or(last, |end| end.min(last))),
+            ..range
+        }
+    }
+}
+
+impl MaxAudioBytes {
+    fn check(self, size: u64) -> Result<(), Error> {
+        match self.0 {
+            Some(limit) if size > limit => {
+                tracing::warn!(size, limit, "refusing to serve oversized audio");
+                Err(Error::TooLarge { size, limit })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+const AUDIO_EXPOSE_HEADERS: &str = "Accept-Ranges, Content-Range, X-Origin-Type, X-Origin-Size, X-Duration-Seconds, X-Duration-Millis, X-Audio-Quality, X-Sample-Rate, X-Bit-Depth, X-Channels";
+
+fn origin_headers(info: &AudioInfo, stream: Option<&StreamInfo>) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "X-Origin-Type",
+            String::from(audio_mime_type(&info.extension)),
+        ),
         ("X-Origin-Size", format!("{}", info.size)),
         ("X-Duration-Seconds", format!("{}", info.duration)),
-        ("X-Audio-Quality", String::from("lossless")),
+        (
+            "X-Audio-Quality",
+            String::from(audio_quality(&info.extension, stream)),
+        ),
+    ]
+}
+
+/// Serves audio the provider streams instead of linking to.
+///
+/// Answers with `206 Partial Content` if the provider returned part of the file, which it may
+/// not do even if a range was requested.
+fn stream_audio(
+    streamed: StreamedAudio,
+    filename: Option<String>,
+    cache_control: &str,
+) -> Response {
+    let StreamedAudio {
+        audio:
+            AudioResourceReader {
+                info,
+                range,
+                reader,
+            },
+        stream,
+    } = streamed;
+    // providers report the range they send out of the whole file
+    let Some(total) = range.total else {
+        return Error::from(ProviderError::GeneralError).into_response();
+    };
+
+    if total > 0 && range.start >= total {
+        return Error::RangeNotSatisfiable(total).into_response();
+    }
+
+    let end = range
+        .end
+        .map_or(total, |end| end.saturating_add(1).min(total));
+    let partial = range.start > 0 || end < total;
+    let mut headers = vec![
+        (CONTENT_TYPE, String::from(audio_mime_type(&info.extension))),
+        (CONTENT_LENGTH, (end - range.start).to_string()),
+        (ACCEPT_RANGES, String::from("bytes")),
+        (CACHE_CONTROL, String::from(cache_control)),
     ];
+    if let Some(filename) = filename {
+        headers.push((
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{filename}\""),
+        ));
+    }
+    let status = if partial {
+        headers.push((
+            CONTENT_RANGE,
+            format!("bytes {}-{}/{total}", range.start, end - 1),
+        ));
+        StatusCode::PARTIAL_CONTENT
+    } else {
+        StatusCode::OK
+    };
 
-    (header, headers, Redirect::temporary(&uri)).into_response()
+    (
+        status,
+        [(ACCESS_CONTROL_EXPOSE_HEADERS, AUDIO_EXPOSE_HEADERS)],
+        AppendHeaders(headers),
+        AppendHeaders(details_headers(AudioDetails { info, stream })),
+        Body::from_stream(ReaderStream::new(reader)),
+    )
+        .into_response()
 }
 
+/// Names a downloaded track `{album}-{disc}-{track}.{extension}`.
+///
+/// Characters that could break out of the quoted header value or aren't plain ascii are
+/// replaced with `_`.
+fn download_filename(track: &TrackPath, info: &AudioInfo) -> String {
+    format!(
+        "{}-{}-{}.{}",
+        track.album_id, track.disc_id, track.track_id, info.extension
+    )
+    .chars()
+    .map(|c| match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+        _ => '_',
+    })
+    .collect()
+}
+
+fn audio_mime_type(extension: &str) -> &'static str {
+    match extension {
+        "flac" => "audio/flac",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "opus" | "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Reads the `Range` header of a request, returning `None` if it's absent or invalid, which is
+/// answered with the whole file.
+///
+/// Requests for multiple ranges are served the whole file as well, which is allowed for any
+/// `Range` request, instead of with a `multipart/byteranges` response. Players only ever ask
+/// for one range.
+///
+/// A suffix range for the last `n` bytes is returned as `Err(n)`, as it takes the size of the
+/// file to resolve.
+fn request_range(headers: &HeaderMap) -> Result<Option<Range>, u64> {
+    let Some(value) = headers.get(RANGE).and_then(|v| v.to_str().ok()) else {
+        return Ok(None);
+    };
+    match parse_range_header(value) {
+        Ok(range) => Ok(Some(range)),
+        Err(RangeError::Suffix(len)) => Err(len),
+        Err(RangeError::Multiple) => {
+            tracing::warn!(
+                range = value,
+                "multiple ranges requested, serving the full range"
+            );
+            Ok(None)
+        }
+        // a header that can't be understood is ignored, as RFC 9110 allows
+        Err(e) => {
+            tracing::debug!(range = value, error = ?e, "ignoring range");
+            Ok(None)
+        }
+    }
+}
+
+/// Resolves a request for the last `len` bytes of a track into a range from its start, so that
+/// it can be passed on to providers as any other range.
+///
+/// The size is the total of the first byte fetched from the provider, which is the size of the
+/// whole file as the upstream reports it.
+async fn suffix_range<P: AnniURLProvider + Sync>(
+    provider: &P,
+    track: &TrackPath,
+    len: u64,
+) -> Result<Range, Error> {
+    let audio = provider
+        .get_audio(&track.album_id, track.disc_id, track.track_id, FIRST_BYTE)
+        .await
+        .inspect_err(|e| tracing::warn!(error = %e, "failed to read audio size"))?;
+    let size = audio
+        .range
+        .total
+        .ok_or(Error::Upstream(ProviderError::GeneralError))?;
+    suffix_of(size, len)
+}
+
+/// Range of the first byte of a file, which tells its size.
+const FIRST_BYTE: Range = Range {
+    start: 0,
+    end: Some(0),
+    total: None,
+};
+
+/// Resolves the last `len` bytes of a file of `size` bytes into a range from its start.
+fn suffix_of(size: u64, len: u64) -> Result<Range, Error> {
+    if len == 0 || size == 0 {
+        return Err(Error::RangeNotSatisfiable(size));
+    }
+    Ok(Range {
+        start: size.saturating_sub(len),
+        end: Some(size - 1),
+        total: Some(size),
+    })
+}
+
+/// Why a `Range` header can't be served as a single range.
+#[derive(Debug, PartialEq, Eq)]
+enum RangeError {
+    /// not of the form `bytes=<start>-<end>`
+    Malformed,
+    /// the last byte is before the first
+    Inverted,
+    /// several ranges were asked for
+    Multiple,
+    /// the last `n` bytes were asked for, which takes the size of the file to resolve
+    Suffix(u64),
+}
+
+/// Parses a `Range` header of a single range, such as `bytes=1000-2000` or `bytes=1000-`.
+fn parse_range_header(value: &str) -> Result<Range, RangeError> {
+    let ranges = value
+        .trim()
+        .strip_prefix("bytes=")
+        .ok_or(RangeError::Malformed)?;
+    if ranges.contains(',') {
+        return Err(RangeError::Multiple);
+    }
+    let (start, end) = ranges.split_once('-').ok_or(RangeError::Malformed)?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        return Err(RangeError::Suffix(parse_position(end)?));
+    }
+    let start = parse_position(start)?;
+    let end = match end {
+        "" => None,
+        end => Some(parse_position(end)?),
+    };
+    if end.is_some_and(|end| end < start) {
+        return Err(RangeError::Inverted);
+    }
+    Ok(Range {
+        start,
+        end,
+        total: None,
+    })
+}
+
+/// Parses a byte position, which unlike `u64::from_str` takes no sign.
+fn parse_position(position: &str) -> Result<u64, RangeError> {
+    if position.is_empty() || !position.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(RangeError::Malformed);
+    }
+    position.parse().map_err(|_| RangeError::Malformed)
+}
+
+#[tracing::instrument(skip_all, fields(album_id = %album_id, disc_id = ?disc_id))]
 async fn cover_redirect<P: AnniURLProvider + Send + Sync>(
     Path(CoverPath { album_id, disc_id }): Path<CoverPath>,
+    Query(CoverQuery { size }): Query<CoverQuery>,
+    headers: HeaderMap,
+    Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+    Extension(state): Extension<Arc<AnnilState>>,
+    Extension(cache): Extension<Arc<CachePolicy>>,
+    Extension(webp_covers): Extension<Option<Arc<WebpCovers>>>,
+) -> Response {
+    let album_id = match sanitize_album_id(&album_id) {
+        Ok(album_id) => album_id,
+        Err(e) => return e.into_response(),
+    };
+    if size.is_some_and(|size| size == 0 || size > MAX_THUMBNAIL_SIZE) {
+        return Error::BadRequest(format!(
+            "cover size must be between 1 and {MAX_THUMBNAIL_SIZE}"
+        ))
+        .into_response();
+    }
+    // the representation depends on `Accept` whenever covers may be converted
+    let vary = AppendHeaders(webp_covers.is_some().then_some((VARY, "Accept")));
+    let webp_covers = webp_covers.filter(|_| accepts_webp(&headers));
+    let etag = cover_etag(
+        &state.etag.read().await,
+        album_id,
+        disc_id,
+        size,
+        webp_covers.is_some(),
+    );
+    // covers can only have changed when the library did
+    let last_modified = UNIX_EPOCH + Duration::from_secs(*state.last_update.read().await);
+    // only covers served by this server carry validators, links may expire and are redirected to
+    // with their own policy
+    let cache_headers = |etag: &str, last_modified| {
+        [
+            (CACHE_CONTROL, cache.cover.clone()),
+            (ETAG, etag.to_owned()),
+            (LAST_MODIFIED, httpdate::fmt_http_date(last_modified)),
+        ]
+    };
+    if not_modified(&headers, &etag, last_modified) {
+        return (
+            StatusCode::NOT_MODIFIED,
+            cache_headers(&etag, last_modified),
+            vary,
+        )
+            .into_response();
+    }
+    if let Some(Some(webp)) = webp_covers.as_ref().and_then(|covers| covers.get(&etag)) {
+        return (
+            cache_headers(&etag, last_modified),
+            vary,
+            [(CONTENT_TYPE, "image/webp")],
+            webp.to_vec(),
+        )
+            .into_response();
+    }
+
+    let provider = provider.read().await;
+
+    let cover = match size {
+        Some(size) => cover_thumbnail(&*provider, album_id, disc_id, size)
+            .await
+            .map(|cover| cover.map_err(|reader| (reader, Validators::default()))),
+        None => provider.get_cover_validated(album_id, disc_id).await,
+    };
+    // `*` matches any cover that exists, which is only known once it's resolved
+    if matches!(cover, Ok(Err(_))) && if_none_match_any(&headers) {
+        return (
+            StatusCode::NOT_MODIFIED,
+            cache_headers(&etag, last_modified),
+            vary,
+        )
+            .into_response();
+    }
+    let cover = match (cover, webp_covers) {
+        (Ok(Err((reader, validators))), Some(covers)) => {
+            match covers.convert(&etag, reader).await {
+                Ok(Ok(webp)) => {
+                    let webp = webp.to_vec();
+                    let cache_headers = cache_headers(&etag, last_modified);
+                    return (cache_headers, vary, [(CONTENT_TYPE, "image/webp")], webp)
+                        .into_response();
+                }
+                Ok(Err(reader)) => Ok(Err((reader, validators))),
+                Err(e) => Err(ProviderError::from(e)),
+            }
+        }
+        (cover, _) => cover,
+    };
+    match cover {
+        Ok(Ok(uri)) => (
+            [(CACHE_CONTROL, cache.cover_link.as_str())],
+            vary,
+            Redirect::temporary(&uri),
+        )
+            .into_response(),
+        Ok(Err((reader, validators))) => {
+            // covers served as they are carry the upstream's validators where it sent any
+            let etag = validators.etag.unwrap_or(etag);
+            let last_modified = validators.last_modified.unwrap_or(last_modified);
+            let cache_headers = cache_headers(&etag, last_modified);
+            if not_modified(&headers, &etag, last_modified) {
+                return (StatusCode::NOT_MODIFIED, cache_headers, vary).into_response();
+            }
+            match cover_body(reader).await {
+                Ok((content_type, body)) => {
+                    (cache_headers, vary, [(CONTENT_TYPE, content_type)], body).into_response()
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to read cover");
+                    Error::from(ProviderError::from(e)).into_response()
+                }
+            }
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to resolve cover link");
+            Error::from(e).into_response()
+        }
+    }
+}
+
+/// Serves the tags of a FLAC track as json, fetching only the start of the file.
+#[tracing::instrument(skip_all, fields(
+    album_id = %track.album_id,
+    disc_id = track.disc_id.get(),
+    track_id = track.track_id.get(),
+))]
+async fn track_meta<P: AnniURLProvider + Send + Sync>(
+    track: TrackPath,
+    Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+) -> Response {
+    let provider = provider.read().await;
+
+    let range = Range {
+        start: 0,
+        end: Some(MAX_METADATA_SIZE - 1),
+        total: None,
+    };
+    let audio = match provider
+        .get_audio(&track.album_id, track.disc_id, track.track_id, range)
+        .await
+    {
+        Ok(audio) => audio,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to fetch track header");
+            return Error::from(e).into_response();
+        }
+    };
+    if audio.info.extension != "flac" {
+        return Error::NotImplemented("tags can only be read from flac files").into_response();
+    }
+
+    // upstreams ignoring the range send the whole file
+    match read_tags(limit_reader(audio.reader, MAX_METADATA_SIZE)).await {
+        Ok(mut tags) => {
+            // titles from the manifest take precedence over the tags
+            if let Some(titles) = provider.album_titles(&track.album_id) {
+                if let Some(title) = titles.track(track.disc_id, track.track_id) {
+                    tags.title = Some(title.to_owned());
+                }
+                tags.album = titles.title.or(tags.album);
+            }
+            ([(CACHE_CONTROL, "private")], Json(tags)).into_response()
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to read tags");
+            Error::from(e).into_response()
+        }
+    }
+}
+
+/// `Cache-Control` of successful audio and cover responses.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CachePolicy {
+    /// Audio links are usually signed and expire, so they aren't cached by default.
+    #[serde(default = "default_audio_cache_control")]
+    pub audio: String,
+    /// Covers served by this server.
+    #[serde(default = "default_cover_cache_control")]
+    pub cover: String,
+    /// Redirects to cover links, which may expire like audio links.
+    #[serde(default = "default_cover_link_cache_control")]
+    pub cover_link: String,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            audio: default_audio_cache_control(),
+            cover: default_cover_cache_control(),
+            cover_link: default_cover_link_cache_control(),
+        }
+    }
+}
+
+fn default_audio_cache_control() -> String {
+    String::from("private, max-age=0")
+}
+
+fn default_cover_cache_control() -> String {
+    String::from("public, max-age=86400")
+}
+
+fn default_cover_link_cache_control() -> String {
+    String::from("private, max-age=300")
+}
+
+/// Lists albums with annil's handler, tagged with an etag derived from the library etag, so that
+/// clients polling for changes get 304 while the library stays the same.
+///
+/// annil's handler still checks the token and lists the albums, only the body is saved. Share
+/// tokens only list the albums they share, so the etag covers the token as well.
+///
+/// With `offset` or `limit`, a page of the sorted album ids the token can fetch is listed from
+/// the provider and served as [`AlbumPage`].
+async fn albums<P: AnniURLProvider + Send + Sync + 'static>(
+    Query(query): Query<AlbumsQuery>,
+    Extension(state): Extension<Arc<AnnilState>>,
+    Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+    req: Request,
+) -> Response {
+    if query.limit == Some(0) {
+        return Error::BadRequest(String::from("`limit` must be greater than 0")).into_response();
+    }
+    let etag = albums_etag(
+        &state.etag.read().await,
+        req.headers().get(AUTHORIZATION),
+        &query,
+    );
+    // the albums are listed below whatever the headers, so `*` can be matched right away
+    let not_modified = if_none_match(req.headers(), &etag) || if_none_match_any(req.headers());
+
+    if query.offset.is_none() && query.limit.is_none() {
+        let mut response = annil::route::user::albums::<P>.call(req, ()).await;
+        if response.status() != StatusCode::OK {
+            return response;
+        }
+        if not_modified {
+            return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+        }
+        response
+            .headers_mut()
+            .insert(ETAG, HeaderValue::from_str(&etag).unwrap());
+        return response;
+    }
+
+    let (mut parts, _) = req.into_parts();
+    let Ok(claim) = AnnilClaim::from_request_parts(&mut parts, &()).await else {
+        return Error::Unauthorized("user or share").into_response();
+    };
+    let albums = match provider.read().await.albums().await {
+        Ok(albums) => albums,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to list albums");
+            return Error::from(e).into_response();
+        }
+    };
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+    }
+    let mut albums: Vec<_> = albums
+        .into_iter()
+        .filter(|album_id| claim.can_fetch(album_id, None, None))
+        .map(Cow::into_owned)
+        .collect();
+    albums.sort_unstable();
+    ([(ETAG, etag)], Json(AlbumPage::new(albums, query))).into_response()
+}
+
+#[derive(Deserialize)]
+struct AlbumsQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+/// A page of album ids, in the order of the ids.
+#[derive(Serialize)]
+struct AlbumPage {
+    albums: Vec<String>,
+    /// albums on all pages
+    total: usize,
+    /// offset of the next page, if there is one
+    next: Option<usize>,
+}
+
+impl AlbumPage {
+    fn new(mut albums: Vec<String>, query: AlbumsQuery) -> Self {
+        let total = albums.len();
+        let offset = query.offset.unwrap_or(0).min(total);
+        let end = query
+            .limit
+            .map_or(total, |limit| offset.saturating_add(limit).min(total));
+        albums.truncate(end);
+        albums.drain(..offset);
+        Self {
+            albums,
+            total,
+            next: (end < total).then_some(end),
+        }
+    }
+}
+
+fn albums_etag(library_etag: &str, token: Option<&HeaderValue>, query: &AlbumsQuery) -> String {
+    // parts are length-prefixed, so a missing offset or limit differs from any number
+    let page = |n: Option<usize>| n.map_or_else(String::new, |n| n.to_string());
+    stable_etag(&[
+        library_etag.as_bytes(),
+        token.map_or(&[][..], HeaderValue::as_bytes),
+        page(query.offset).as_bytes(),
+        page(query.limit).as_bytes(),
+    ])
+}
+
+#[derive(Serialize)]
+struct AlbumTracks {
+    discs: Vec<DiscTracks>,
+    /// from the manifest, if one is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    titles: Option<AlbumTitles>,
+}
+
+#[tracing::instrument(skip_all, fields(album_id = %album_id))]
+async fn album_tracks<P: AnniURLProvider + Send + Sync>(
+    Path(album_id): Path<String>,
+    Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+    req: Request,
+) -> Response {
+    let album_id = match sanitize_album_id(&album_id) {
+        Ok(album_id) => album_id,
+        Err(e) => return e.into_response(),
+    };
+    // share tokens only list the albums they were issued for
+    let (mut parts, _) = req.into_parts();
+    let Ok(claim) = AnnilClaim::from_request_parts(&mut parts, &()).await else {
+        return Error::Unauthorized("user or share").into_response();
+    };
+    if !claim.can_fetch(album_id, None, None) {
+        return Error::Unauthorized("user or share").into_response();
+    }
+    let provider = provider.read().await;
+
+    match provider.list_tracks(album_id).await {
+        Ok(Some(discs)) if discs.is_empty() => {
+            Error::from(ProviderError::FileNotFound).into_response()
+        }
+        Ok(Some(discs)) => {
+            let titles = provider.album_titles(album_id);
+            (
+                [(CACHE_CONTROL, "private")],
+                Json(AlbumTracks { discs, titles }),
+            )
+                .into_response()
+        }
+        // an album is taken to be missing without its first track
+        Ok(None) => match provider
+            .exists(album_id, NonZeroU8::MIN, NonZeroU8::MIN)
+            .await
+        {
+            Ok(false) => Error::from(ProviderError::FileNotFound).into_response(),
+            Ok(true) => Error::NotImplemented("the provider can't list tracks").into_response(),
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to look up the first track");
+                Error::from(e).into_response()
+            }
+        },
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to list tracks");
+            Error::from(e).into_response()
+        }
+    }
+}
+
+/// Answers with the headers of a cover, without its body.
+///
+/// Linked covers are redirected to as for GET, so that the upstream reports their size. Covers
+/// served by this server only tell their `Content-Length` if it's known without reading them,
+/// such as for converted covers.
+async fn cover_head<P: AnniURLProvider + Send + Sync>(
+    path: Path<CoverPath>,
+    query: Query<CoverQuery>,
+    headers: HeaderMap,
+    provider: Extension<Arc<AnnilProvider<P>>>,
+    state: Extension<Arc<AnnilState>>,
+    cache: Extension<Arc<CachePolicy>>,
+    webp_covers: Extension<Option<Arc<WebpCovers>>>,
+) -> Response {
+    let response =
+        cover_redirect::<P>(path, query, headers, provider, state, cache, webp_covers).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    if let Some(len) = body.size_hint().exact() {
+        parts.headers.insert(CONTENT_LENGTH, len.into());
+    }
+    Response::from_parts(parts, Body::empty())
+}
+
+/// Resolves a thumbnail of a cover.
+///
+/// Covers of providers that can't resize them are resized here if the `thumbnails` feature is
+/// enabled, and served in full otherwise.
+async fn cover_thumbnail<P: AnniURLProvider + Sync>(
+    provider: &P,
+    album_id: &str,
+    disc_id: Option<NonZeroU8>,
+    size: u32,
+) -> anni_provider::Result<Result<String, ResourceReader>> {
+    if let Some(cover) = provider
+        .get_cover_thumbnail_link(album_id, disc_id, size)
+        .await?
+    {
+        return Ok(cover);
+    }
+
+    #[cfg(feature = "thumbnails")]
+    {
+        let cover = provider.get_cover(album_id, disc_id).await?;
+        let thumbnail = resize_cover(cover, size).await?;
+        Ok(Err(Box::pin(Cursor::new(thumbnail))))
+    }
+    #[cfg(not(feature = "thumbnails"))]
+    provider.get_cover_link(album_id, disc_id).await
+}
+
+/// Scales a cover down to fit in a `size`x`size` square, re-encoding it as JPEG.
+///
+/// Covers that already fit are returned as they are.
+#[cfg(feature = "thumbnails")]
+async fn resize_cover(mut reader: ResourceReader, size: u32) -> std::io::Result<Vec<u8>> {
+    let mut cover = Vec::new();
+    reader.read_to_end(&mut cover).await?;
+
+    tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&cover).map_err(std::io::Error::other)?;
+        if image.width() <= size && image.height() <= size {
+            return Ok(cover);
+        }
+
+        let mut thumbnail = Vec::new();
+        image
+            .thumbnail(size, size)
+            .to_rgb8()
+            .write_with_encoder(image::codecs::jpeg::JpegEncoder::new(&mut thumbnail))
+            .map_err(std::io::Error::other)?;
+        Ok(thumbnail)
+    })
+    .await?
+}
+
+/// Streams a cover, with its content type sniffed from the first bytes.
+async fn cover_body(mut reader: ResourceReader) -> std::io::Result<(&'static str, Body)> {
+    let mut magic = Vec::with_capacity(12);
+    (&mut reader).take(12).read_to_end(&mut magic).await?;
+    let content_type = image_mime_type(&magic);
+    let reader = Cursor::new(magic).chain(reader);
+    Ok((content_type, Body::from_stream(ReaderStream::new(reader))))
+}
+
+fn image_mime_type(magic: &[u8]) -> &'static str {
+    match magic {
+        [0xff, 0xd8, 0xff, ..] => "image/jpeg",
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Largest cover converted to WebP, larger ones are streamed as they are.
+const MAX_WEBP_SOURCE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Covers converted to WebP for clients that accept it, by cover etag.
+///
+/// `None` marks covers that couldn't be converted, were too large or didn't get any smaller,
+/// which are served as they are.
+struct WebpCovers(Mutex<LruCache<String, Option<Arc<[u8]>>>>);
+
+impl WebpCovers {
+    fn new(capacity: NonZeroUsize) -> Self {
+        Self(Mutex::new(LruCache::new(capacity)))
+    }
+
+    fn get(&self, etag: &str) -> Option<Option<Arc<[u8]>>> {
+        self.0.lock().unwrap().get(etag).cloned()
+    }
+
+    /// Converts a cover, or hands it back if it can't be served as a smaller WebP.
+    async fn convert(
+        &self,
+        etag: &str,
+        mut reader: ResourceReader,
+    ) -> std::io::Result<Result<Arc<[u8]>, ResourceReader>> {
+        if let Some(None) = self.get(etag) {
+            return Ok(Err(reader));
+        }
+
+        // read one byte past the limit to tell whether the cover fits
+        let mut cover = Vec::new();
+        (&mut reader)
+            .take(MAX_WEBP_SOURCE_SIZE as u64 + 1)
+            .read_to_end(&mut cover)
+            .await?;
+        if cover.len() > MAX_WEBP_SOURCE_SIZE {
+            self.0.lock().unwrap().put(etag.to_owned(), None);
+            return Ok(Err(Box::pin(Cursor::new(cover).chain(reader))));
+        }
+
+        let cover: Arc<[u8]> = cover.into();
+        let webp = encode_webp(cover.clone())
+            .await
+            .filter(|webp| webp.len() < cover.len())
+            .map(Arc::from);
+        self.0.lock().unwrap().put(etag.to_owned(), webp.clone());
+        Ok(webp.ok_or_else(|| Box::pin(Cursor::new(cover)) as ResourceReader))
+    }
+}
+
+/// Re-encodes a cover as WebP.
+///
+/// The `image` crate only writes lossless WebP, which mostly pays off for PNG covers.
+#[cfg(feature = "webp-covers")]
+async fn encode_webp(cover: Arc<[u8]>) -> Option<Vec<u8>> {
+    tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&cover).ok()?;
+        // the encoder takes 8-bit color only
+        let image = if image.color().has_alpha() {
+            image::DynamicImage::from(image.to_rgba8())
+        } else {
+            image::DynamicImage::from(image.to_rgb8())
+        };
+        let mut webp = Vec::new();
+        image
+            .write_with_encoder(image::codecs::webp::WebPEncoder::new_lossless(&mut webp))
+            .ok()?;
+        Some(webp)
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+#[cfg(not(feature = "webp-covers"))]
+async fn encode_webp(_cover: Arc<[u8]>) -> Option<Vec<u8>> {
+    None
+}
+
+/// Checks whether the `Accept` header of a request lists WebP, other than with `q=0`.
+fn accepts_webp(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|media_range| {
+            let mut params = media_range.split(';');
+            let webp = params
+                .next()
+                .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("image/webp"));
+            webp && !params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            })
+        })
+}
+
+/// Derives a cover etag from the library etag, so that covers are revalidated after a reload.
+fn cover_etag(
+    library_etag: &str,
+    album_id: &str,
+    disc_id: Option<NonZeroU8>,
+    size: Option<u32>,
+    webp: bool,
+) -> String {
+    stable_etag(&[
+        library_etag.as_bytes(),
+        album_id.as_bytes(),
+        &[disc_id.map_or(0, NonZeroU8::get)],
+        &size.unwrap_or(0).to_le_bytes(),
+        &[webp as u8],
+    ])
+}
+
+/// Hashes `parts` into an etag with 64-bit FNV-1a, which unlike the std hashers is the same
+/// across builds, so that etags survive upgrading the server.
+fn stable_etag(parts: &[&[u8]]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        // the length keeps the bytes of neighbouring parts from running together
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(*part) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("\"{hash:016x}\"")
+}
+
+/// Etag of the library, which changes whenever an album is added or removed.
+fn library_etag(albums: &HashSet<String>) -> String {
+    let mut albums: Vec<_> = albums.iter().map(String::as_bytes).collect();
+    albums.sort_unstable();
+    stable_etag(&albums)
+}
+
+/// Checks whether a conditional request can be answered with `304 Not Modified`.
+///
+/// The date is less precise than the etag, so it's only compared without an etag.
+fn not_modified(headers: &HeaderMap, etag: &str, last_modified: SystemTime) -> bool {
+    if headers.contains_key(IF_NONE_MATCH) {
+        if_none_match(headers, etag)
+    } else {
+        if_modified_since(headers, last_modified)
+    }
+}
+
+/// Checks whether the `If-None-Match` header of a request lists `etag`, compared weakly.
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match_tags(headers).any(|tag| tag == etag)
+}
+
+/// Checks whether the `If-None-Match` header of a request is `*`, which matches whatever exists.
+fn if_none_match_any(headers: &HeaderMap) -> bool {
+    if_none_match_tags(headers).any(|tag| tag == "*")
+}
+
+fn if_none_match_tags(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .into_iter()
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+}
+
+/// Checks whether the `If-Modified-Since` header of a request is no earlier than `last_modified`.
+fn if_modified_since(headers: &HeaderMap, last_modified: SystemTime) -> bool {
+    headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        .is_some_and(|since| since >= last_modified)
+}
+
+const HEALTHZ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Health {
+    /// unix timestamp of the last successful check, 0 if none succeeded yet
+    last_success: AtomicU64,
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    provider: &'static str,
+    last_success: Option<u64>,
+}
+
+async fn healthz<P: AnniURLProvider + Send + Sync>(
+    Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+    Extension(health): Extension<Arc<Health>>,
+) -> Response {
+    let provider = provider.read().await;
+
+    let status = match tokio::time::timeout(HEALTHZ_TIMEOUT, provider.albums()).await {
+        Ok(Ok(_)) => {
+            health.last_success.store(unix_now(), Ordering::Relaxed);
+            StatusCode::OK
+        }
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    let last_success = match health.last_success.load(Ordering::Relaxed) {
+        0 => None,
+        time => Some(time),
+    };
+    let provider = std::any::type_name::<P>().rsplit("::").next().unwrap();
+
+    (
+        status,
+        [(CACHE_CONTROL, "no-store")],
+        Json(HealthReport {
+            provider,
+            last_success,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Serialize)]
+struct Stats {
+    albums: usize,
+    /// `None` if the provider can't count tracks cheaply
+    tracks: Option<usize>,
+    etag: String,
+    last_update: u64,
+}
+
+/// Summarizes the library, as a quick check after a reload.
+///
+/// Tracks are only counted by providers that list them cheaply, as it takes a listing per album.
+async fn stats<P: AnniURLProvider + Send + Sync>(
     Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+    Extension(state): Extension<Arc<AnnilState>>,
 ) -> Response {
     let provider = provider.read().await;
 
-    let uri = match provider.get_cover_link(&album_id, disc_id).await {
-        Ok(Ok(uri)) => uri,
+    let albums = match provider.albums().await {
+        Ok(albums) => albums,
         Err(e) => return Error::from(e).into_response(),
-        _ => return (StatusCode::NOT_FOUND, [(CACHE_CONTROL, "private")]).into_response(),
     };
-    Redirect::temporary(&uri).into_response()
+    let tracks = if provider.lists_tracks_cheaply() {
+        let mut tracks = 0;
+        for album_id in &albums {
+            match provider.list_tracks(album_id).await {
+                Ok(Some(discs)) => {
+                    tracks += discs.iter().map(|disc| disc.tracks.len()).sum::<usize>()
+                }
+                Ok(None) => {}
+                Err(e) => return Error::from(e).into_response(),
+            }
+        }
+        Some(tracks)
+    } else {
+        None
+    };
+
+    let stats = Stats {
+        albums: albums.len(),
+        tracks,
+        etag: state.etag.read().await.clone(),
+        last_update: *state.last_update.read().await,
+    };
+    ([(CACHE_CONTROL, "no-store")], Json(stats)).into_response()
+}
+
+#[derive(Serialize)]
+struct Version {
+    version: &'static str,
+    git_hash: &'static str,
+    build_timestamp: Option<u64>,
+}
+
+async fn version() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+        build_timestamp: env!("BUILD_TIMESTAMP").parse().ok(),
+    })
+}
+
+/// Records the count and latency of requests per route and status.
+async fn track_metrics(matched_path: Option<MatchedPath>, req: Request, next: Next) -> Response {
+    let route = match matched_path {
+        Some(path) => path.as_str().to_owned(),
+        None => String::from("unknown"),
+    };
+    let method = req.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let labels = vec![
+        Label::new("method", method),
+        Label::new("route", route),
+        Label::new("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", labels.clone()).increment(1);
+    metrics::histogram!("http_request_duration_seconds", labels).record(elapsed);
+
+    response
+}
+
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// Runs a request in a span carrying its id, and echoes the id in `X-Request-Id`.
+///
+/// The id is taken from the `X-Request-Id` header set by a proxy in front, or generated if it's
+/// absent or unreasonable.
+async fn request_id(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(X_REQUEST_ID)
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .cloned()
+        .unwrap_or_else(|| {
+            let id = format!("{:032x}", rand::random::<u128>());
+            HeaderValue::try_from(id).unwrap()
+        });
+    req.headers_mut().insert(X_REQUEST_ID, id.clone());
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = id.to_str().unwrap_or_default(),
+        method = %req.method(),
+        // the query may carry a token in `auth`, which must not end up in logs
+        path = req.uri().path(),
+    );
+    let mut response = next.run(req).instrument(span).await;
+    response.headers_mut().insert(X_REQUEST_ID, id);
+    response
+}
+
+/// Rejects admin requests without the admin token, comparing it in constant time.
+///
+/// annil checks the token again when handling the request, but makes no promise about timing.
+async fn require_admin(
+    Extension(key): Extension<Arc<AnnilKeys>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let authorized = req
+        .headers()
+        .get(AUTHORIZATION)
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(key.admin_token.as_bytes())));
+    if !authorized {
+        return Error::Unauthorized("admin").into_response();
+    }
+    next.run(req).await
+}
+
+/// Rejects requests without a valid user or share token, checked with the keys annil signs them
+/// with.
+async fn require_token(mut req: Request, next: Next) -> Response {
+    if req.extract_parts::<AnnilClaim>().await.is_err() {
+        return Error::Unauthorized("user or share").into_response();
+    }
+    next.run(req).await
+}
+
+/// Logs admin requests, warning on and counting the ones rejected for a bad admin token.
+async fn audit_admin(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    matched_path: Option<MatchedPath>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let remote = match connect_info {
+        Some(ConnectInfo(addr)) => addr.to_string(),
+        None => String::from("unknown"),
+    };
+    let route = match matched_path {
+        Some(path) => path.as_str().to_owned(),
+        None => String::from("unknown"),
+    };
+
+    let response = next.run(req).await;
+
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        tracing::warn!(%remote, %route, %status, "rejected admin request");
+        metrics::counter!("admin_auth_rejections_total", "route" => route).increment(1);
+    } else {
+        tracing::info!(%remote, %route, %status, "admin request");
+    }
+
+    response
+}
+
+/// Token bucket limits applied to each client ip.
+///
+/// Clients are told apart by the address they connect from, so clients behind a reverse proxy
+/// share one bucket and requests over unix sockets are not limited, unless
+/// `trust_forwarded_for` is set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimit {
+    /// Rate at which tokens are refilled.
+    pub requests_per_second: f64,
+    /// Most requests a client may send at once.
+    pub burst: u32,
+    /// Take the client ip from the last `X-Forwarded-For` entry, which is only safe behind a
+    /// reverse proxy setting it, as clients can send anything.
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+}
+
+/// Clients tracked before the least recently seen ones are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<LruCache<IpAddr, TokenBucket>>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(LruCache::new(
+                NonZeroUsize::new(MAX_TRACKED_CLIENTS).unwrap(),
+            )),
+        }
+    }
+
+    fn refill(&self, bucket: &mut TokenBucket, now: Instant) {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.limit.requests_per_second).min(self.limit.burst as f64);
+        bucket.updated = now;
+    }
+
+    /// Takes a token of `ip`, or returns how long it takes until one is available.
+    fn acquire(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        // the client seen least recently is the most likely to have a full bucket again, which is
+        // the same as an untracked one
+        let bucket = buckets.get_or_insert_mut(ip, || TokenBucket {
+            tokens: self.limit.burst as f64,
+            updated: now,
+        });
+        self.refill(bucket, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.limit.requests_per_second,
+            ))
+        }
+    }
+}
+
+/// Address the reverse proxy in front of the server got a request from, which it appends to
+/// `X-Forwarded-For`.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    let forwarded = headers.get_all("x-forwarded-for").into_iter().last()?;
+    forwarded
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Rejects clients exceeding the rate limit with `429 Too Many Requests`.
+///
+/// Requests without a client ip, such as those over unix sockets without a trusted
+/// `X-Forwarded-For`, are not limited.
+async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let forwarded = limiter
+        .limit
+        .trust_forwarded_for
+        .then(|| forwarded_for(req.headers()))
+        .flatten();
+    let ip = forwarded.or(connect_info.map(|ConnectInfo(addr)| addr.ip()));
+    if let Some(ip) = ip {
+        if let Err(wait) = limiter.acquire(ip) {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            return Error::RateLimited(retry_after).into_response();
+        }
+    }
+    next.run(req).await
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Computes the etag at startup.
+///
+/// If the provider is unreachable, a placeholder is used so that the server can still start,
+/// and the real etag is picked up by the next reload.
+async fn initial_etag<P: AnniProvider + Send + Sync>(provider: &AnnilProvider<P>) -> String {
+    match list_albums(provider).await {
+        Ok(albums) => library_etag(&albums),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to compute etag, starting with a placeholder");
+            String::new()
+        }
+    }
+}
+
+/// Tracks reloads holding the provider exclusively, so that requests are turned away instead of
+/// queueing behind them.
+#[derive(Default)]
+pub struct ReloadStatus {
+    /// reloads waiting for or holding the write lock
+    reloading: AtomicUsize,
+}
+
+impl ReloadStatus {
+    fn is_reloading(&self) -> bool {
+        self.reloading.load(Ordering::Acquire) > 0
+    }
+
+    fn begin(&self) -> Reloading<'_> {
+        self.reloading.fetch_add(1, Ordering::AcqRel);
+        Reloading(self)
+    }
+}
+
+/// Marks a reload in progress until dropped.
+struct Reloading<'a>(&'a ReloadStatus);
+
+impl Drop for Reloading<'_> {
+    fn drop(&mut self) {
+        self.0.reloading.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Answers 503 while a reload holds the provider, instead of leaving requests waiting for it.
+async fn unavailable_while_reloading(
+    Extension(status): Extension<Arc<ReloadStatus>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if status.is_reloading() {
+        return Error::Reloading.into_response();
+    }
+    next.run(req).await
+}
+
+/// Album ids as of the last reload, which a dry-run reload is compared against.
+#[derive(Default)]
+pub struct AlbumSnapshot {
+    albums: std::sync::RwLock<HashSet<String>>,
+    /// notified every time the snapshot is captured
+    captured: Notify,
+}
+
+impl AlbumSnapshot {
+    /// Replaces the snapshot with the albums the provider lists now.
+    pub async fn capture<P: AnniProvider + Send + Sync>(
+        &self,
+        provider: &AnnilProvider<P>,
+    ) -> Result<(), ProviderError> {
+        let albums = list_albums(provider).await?;
+        self.replace(albums);
+        Ok(())
+    }
+
+    fn replace(&self, albums: HashSet<String>) {
+        *self.albums.write().unwrap() = albums;
+        self.captured.notify_one();
+    }
+}
+
+async fn list_albums<P: AnniProvider + Send + Sync>(
+    provider: &AnnilProvider<P>,
+) -> Result<HashSet<String>, ProviderError> {
+    Ok(provider
+        .read()
+        .await
+        .albums()
+        .await?
+        .into_iter()
+        .map(Cow::into_owned)
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct ReloadQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct ReloadDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    etag_changed: bool,
+}
+
+/// How long computing the etag may take before a reload is given up.
+#[derive(Clone, Copy)]
+struct EtagTimeout(Duration);
+
+/// Reloads the provider, or with `?dry_run=true` reports what a reload would change.
+///
+/// A dry run lists albums without clearing provider caches, and commits nothing.
+///
+/// The provider is only locked for writing once the reload has been prepared, so that
+/// requests aren't stalled while albums are listed.
+async fn admin_reload<P: AnniURLProvider + Send + Sync + 'static>(
+    Query(query): Query<ReloadQuery>,
+    Extension(provider): Extension<Arc<AnnilProvider<P>>>,
+    Extension(state): Extension<Arc<AnnilState>>,
+    Extension(snapshot): Extension<Arc<AlbumSnapshot>>,
+    Extension(EtagTimeout(etag_timeout)): Extension<EtagTimeout>,
+    Extension(status): Extension<Arc<ReloadStatus>>,
+) -> Response {
+    // the token has been checked by `require_admin`
+    if !query.dry_run {
+        return match reload_state(&provider, &state, &snapshot, &status, etag_timeout).await {
+            Ok(()) => StatusCode::OK.into_response(),
+            Err(e) => {
+                tracing::warn!(error = %e, "reload failed, keeping current state");
+                Error::from(e).into_response()
+            }
+        };
+    }
+
+    let albums = match tokio::time::timeout(etag_timeout, list_albums(&provider)).await {
+        Ok(Ok(albums)) => albums,
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "dry-run reload failed");
+            return Error::from(e).into_response();
+        }
+        Err(_) => {
+            tracing::warn!("dry-run reload timed out listing albums");
+            return Error::from(ReloadError::EtagTimeout).into_response();
+        }
+    };
+    let etag = library_etag(&albums);
+    let (mut added, mut removed) = {
+        let snapshot = snapshot.albums.read().unwrap();
+        (
+            albums.difference(&snapshot).cloned().collect::<Vec<_>>(),
+            snapshot.difference(&albums).cloned().collect::<Vec<_>>(),
+        )
+    };
+    added.sort_unstable();
+    removed.sort_unstable();
+
+    Json(ReloadDiff {
+        added,
+        removed,
+        etag_changed: *state.etag.read().await != etag,
+    })
+    .into_response()
+}
+
+/// Why a reload was given up.
+#[derive(Debug)]
+pub enum ReloadError {
+    Provider(ProviderError),
+    /// Listing the albums the etag is computed from took longer than allowed
+    EtagTimeout,
+    /// The metadata repository couldn't be updated
+    Metadata(String),
+}
+
+impl From<ProviderError> for ReloadError {
+    fn from(error: ProviderError) -> Self {
+        Self::Provider(error)
+    }
+}
+
+impl std::fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Provider(error) => error.fmt(f),
+            Self::EtagTimeout => f.write_str("timed out listing albums for the etag"),
+            Self::Metadata(error) => write!(f, "failed to load metadata: {error}"),
+        }
+    }
+}
+
+impl From<ReloadError> for Error {
+    fn from(error: ReloadError) -> Self {
+        match error {
+            ReloadError::Provider(error) => error.into(),
+            ReloadError::EtagTimeout => Self::Timeout,
+            ReloadError::Metadata(_) => Self::Upstream(ProviderError::GeneralError),
+        }
+    }
+}
+
+/// Clones the metadata repository, or pulls it if `pull` is set, and regenerates the database
+/// annil reads album info from.
+///
+/// The clone is kept at `{base}/repo` and the database at `{base}/repo.db`, where annil looks for
+/// them.
+pub async fn load_metadata(metadata: &MetadataConfig) -> Result<(), String> {
+    let metadata = metadata.clone();
+    tokio::task::spawn_blocking(move || {
+        let root = metadata.base.join("repo");
+        let repo = if !root.exists() {
+            RepositoryManager::clone(&metadata.repo, &root)?
+        } else if metadata.pull {
+            RepositoryManager::pull(&root, &metadata.branch)?
+        } else {
+            RepositoryManager::new(&root)?
+        };
+        repo.into_owned_manager()?
+            .to_database(&metadata.base.join("repo.db"))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())
+}
+
+/// Reloads the metadata repository and the provider, and refreshes the etag and album snapshot.
+///
+/// The etag is computed from the albums listed while preparing the reload, which is given up if
+/// it takes longer than `etag_timeout`. The provider is only swapped once that succeeded, so a
+/// failed reload leaves it serving under the old etag.
+pub async fn reload_state<P: AnniURLProvider + Send + Sync>(
+    provider: &AnnilProvider<P>,
+    state: &AnnilState,
+    snapshot: &AlbumSnapshot,
+    status: &ReloadStatus,
+    etag_timeout: Duration,
+) -> Result<(), ReloadError> {
+    if let Some(metadata) = &state.metadata {
+        load_metadata(metadata)
+            .await
+            .map_err(ReloadError::Metadata)?;
+    }
+    // readers are only blocked while the prepared state is swapped in
+    let albums = tokio::time::timeout(etag_timeout, async {
+        provider.read().await.prepare_reload().await
+    })
+    .await
+    .map_err(|_| ReloadError::EtagTimeout)??;
+    let etag = library_etag(&albums);
+    {
+        // set before waiting for the lock, as requests queue behind a waiting writer as well
+        let _reloading = status.begin();
+        provider.write().await.reload().await?;
+    }
+    snapshot.replace(albums);
+
+    let mut current = state.etag.write().await;
+    if *current != etag {
+        tracing::info!(old = %*current, new = %etag, "etag changed");
+        *current = etag;
+        *state.last_update.write().await = unix_now();
+    }
+    Ok(())
+}
+
+/// Spawns a task reloading the provider every `interval`.
+pub fn spawn_reload_task<P: AnniURLProvider + Send + Sync + 'static>(
+    provider: Arc<AnnilProvider<P>>,
+    state: Arc<AnnilState>,
+    snapshot: Arc<AlbumSnapshot>,
+    status: Arc<ReloadStatus>,
+    interval: Duration,
+    etag_timeout: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // the first tick completes immediately, and the state is fresh at startup
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let reloaded = reload_state(&provider, &state, &snapshot, &status, etag_timeout).await;
+            if let Err(e) = reloaded {
+                tracing::warn!(error = %e, "periodic reload failed, keeping current state");
+            }
+        }
+    })
+}
+
+/// Spawns a task resolving the cover of every album, one every `interval`, so that link and
+/// cover caches are filled before clients ask.
+///
+/// Covers are resolved whenever the album snapshot is captured, that is at startup and after
+/// every reload.
+pub fn spawn_prewarm_task<P: AnniURLProvider + Send + Sync + 'static>(
+    provider: Arc<AnnilProvider<P>>,
+    snapshot: Arc<AlbumSnapshot>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            // a capture before the task started is remembered
+            snapshot.captured.notified().await;
+            let albums: Vec<_> = snapshot.albums.read().unwrap().iter().cloned().collect();
+            let mut ticker = tokio::time::interval(interval);
+            for album_id in &albums {
+                ticker.tick().await;
+                // the lock is taken per album, so that reloads aren't held up by the whole run
+                if let Err(e) = provider.read().await.get_cover_link(album_id, None).await {
+                    tracing::debug!(%album_id, error = %e, "failed to prewarm cover");
+                }
+            }
+            tracing::info!(albums = albums.len(), "prewarmed covers");
+        }
+    })
 }
 
 pub async fn make_state<P: AnniProvider + Send + Sync>(
     version: String,
     provider: &AnnilProvider<P>,
+    metadata: Option<MetadataConfig>,
 ) -> AnnilState {
     AnnilState {
         version,
-        last_update: RwLock::new(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-        ),
-        etag: RwLock::new(provider.compute_etag().await.unwrap()),
-        metadata: None,
+        last_update: RwLock::new(unix_now()),
+        etag: RwLock::new(initial_etag(provider).await),
+        metadata,
+    }
+}
+
+/// Cross-origin policy for a group of routes.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct CorsPolicy {
+    /// Origins allowed to access the routes, any origin if unset.
+    pub allowed_origins: Option<Vec<String>>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+}
+
+impl CorsPolicy {
+    /// Checks the policy configured under `name`, such as `cors.admin`.
+    pub fn validate(&self, name: &str, errors: &mut Vec<String>) {
+        match &self.allowed_origins {
+            Some(origins) => {
+                for origin in origins.iter().filter(|origin| !is_origin(origin)) {
+                    errors.push(format!(
+                        "`{name}.allowed_origins` must hold origins like `https://example.com` ({origin})"
+                    ));
+                }
+            }
+            None if self.allow_credentials => errors.push(format!(
+                "`{name}.allow_credentials` needs `{name}.allowed_origins` to be set"
+            )),
+            None => {}
+        }
+    }
+
+    fn layer(&self) -> cors::CorsLayer {
+        let layer = cors::CorsLayer::new()
+            .allow_methods([Method::GET, Method::OPTIONS, Method::POST])
+            .allow_credentials(self.allow_credentials);
+
+        // wildcards are not allowed together with credentials
+        let layer = match (&self.allowed_origins, self.allow_credentials) {
+            (Some(origins), _) => layer.allow_origin(
+                origins
+                    .iter()
+                    .filter_map(|origin| origin.parse::<HeaderValue>().ok())
+                    .collect::<Vec<_>>(),
+            ),
+            (None, false) => layer.allow_origin(cors::Any),
+            // credentials are only shared with listed origins
+            (None, true) => layer.allow_origin(Vec::<HeaderValue>::new()),
+        };
+        if self.allow_credentials {
+            layer.allow_headers(cors::AllowHeaders::mirror_request())
+        } else {
+            layer.allow_headers(cors::Any)
+        }
+    }
+}
+
+/// Whether `origin` is a scheme and host, with an optional port and nothing else.
+fn is_origin(origin: &str) -> bool {
+    match origin.parse::<Uri>() {
+        Ok(uri) => {
+            uri.scheme().is_some()
+                && uri.authority().is_some()
+                && matches!(
+                    uri.path_and_query().map(|path| path.as_str()),
+                    None | Some("/")
+                )
+                && !origin.ends_with('/')
+        }
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct CorsConfig {
+    #[serde(flatten)]
+    pub public: CorsPolicy,
+    /// Policy for admin routes, the public policy is used if unset.
+    pub admin: Option<CorsPolicy>,
+}
+
+/// Options for building the routers.
+#[derive(Default)]
+pub struct AppOptions {
+    pub cors: CorsConfig,
+    /// Include admin routes in the public router.
+    ///
+    /// Otherwise they should be served by [`make_admin_app`] on a separate listener.
+    pub with_admin: bool,
+    /// Serve prometheus metrics on `/metrics` and record request metrics.
+    pub metrics: Option<PrometheusHandle>,
+    /// Albums as of the last reload, shared with [`spawn_reload_task`].
+    pub albums: Arc<AlbumSnapshot>,
+    /// Shared with [`spawn_reload_task`], public routes answer 503 while it reloads.
+    pub reload_status: Arc<ReloadStatus>,
+    /// Limit requests of each client ip to public routes.
+    pub rate_limit: Option<RateLimit>,
+    /// Leave out `/admin/sign`, for deployments that hand out tokens themselves.
+    pub without_sign: bool,
+    /// Give up reloads whose albums take longer than this to list, as the etag is computed from
+    /// them.
+    pub etag_timeout: Duration,
+    /// Stream audio instead of redirecting to the links of the provider.
+    pub proxy_audio: bool,
+    /// Refuse audio files larger than this with 413.
+    pub max_audio_bytes: Option<u64>,
+    /// Stream at most this many bytes of audio per request.
+    pub max_bytes_per_request: Option<NonZeroU64>,
+    /// Serve `/stats` publicly instead of with the admin routes.
+    pub public_stats: bool,
+    /// Serve covers without a token.
+    pub public_covers: bool,
+    /// Covers converted to WebP kept in memory, 0 serves covers as they are.
+    ///
+    /// Conversion needs the `webp-covers` feature.
+    pub webp_cover_entries: usize,
+    /// Path all routes are mounted under, such as `/music`, without a trailing slash.
+    pub base_path: Option<String>,
+    pub cache_control: CachePolicy,
+}
+
+fn admin_routes<P: AnniURLProvider + Send + Sync + 'static>(options: &AppOptions) -> Router {
+    let cors = &options.cors;
+    let router = Router::new().route("/admin/reload", post(admin_reload::<P>));
+    let router = if options.without_sign {
+        router
+    } else {
+        router.route("/admin/sign", post(annil::route::admin::sign))
+    };
+    let router = if options.public_stats {
+        router
+    } else {
+        router.route("/stats", get(stats::<P>))
+    };
+    let router = router
+        .route_layer(middleware::from_fn(require_admin))
+        .route_layer(middleware::from_fn(audit_admin))
+        .layer(Extension(options.albums.clone()))
+        .layer(Extension(options.reload_status.clone()))
+        .layer(Extension(EtagTimeout(options.etag_timeout)))
+        .layer(cors.admin.as_ref().unwrap_or(&cors.public).layer());
+
+    with_metrics(router, options)
+}
+
+fn with_metrics(router: Router, options: &AppOptions) -> Router {
+    match options.metrics {
+        Some(_) => router.route_layer(middleware::from_fn(track_metrics)),
+        None => router,
+    }
+}
+
+fn with_base_path(router: Router, options: &AppOptions) -> Router {
+    match &options.base_path {
+        Some(path) => Router::new().nest(path, router),
+        None => router,
     }
 }
 
+fn with_state<P: AnniURLProvider + Send + Sync + 'static>(
+    router: Router,
+    provider: Arc<AnnilProvider<P>>,
+    initial_state: Arc<AnnilState>,
+    key: Arc<AnnilKeys>,
+) -> Router {
+    router
+        .layer(ServiceBuilder::new().layer(Extension(initial_state)))
+        .layer(Extension(provider))
+        .layer(Extension(key))
+}
+
+/// Builds the public api.
 pub fn make_app<P: AnniURLProvider + Send + Sync + 'static>(
     provider: Arc<AnnilProvider<P>>,
     initial_state: Arc<AnnilState>,
     key: Arc<AnnilKeys>,
+    options: &AppOptions,
 ) -> Router {
-    let router = Router::new()
+    // album lists get large, so json responses are compressed
+    let json_routes = Router::new()
         .route("/info", get(annil::route::user::info))
+        .route("/version", get(version))
+        .route("/albums", get(albums::<P>))
+        .route("/:album_id", get(album_tracks::<P>))
+        .route("/:album_id/:disc_id/:track_id/meta", get(track_meta::<P>));
+    let json_routes = if options.public_stats {
+        json_routes.route("/stats", get(stats::<P>))
+    } else {
+        json_routes
+    };
+    let json_routes = json_routes.layer(CompressionLayer::new());
+    let cover_routes = Router::new()
         .route(
-            "/albums",
-            get(annil::route::user::albums::<SeafileProvider>),
+            "/:album_id/cover",
+            get(cover_redirect::<P>).head(cover_head::<P>),
         )
-        .route("/:album_id/cover", get(cover_redirect::<P>))
         .route(
             "/:album_id/:disc_id/cover",
-            get(cover_redirect::<P>),
-        )
+            get(cover_redirect::<P>).head(cover_head::<P>),
+        );
+    let cover_routes = if options.public_covers {
+        cover_routes
+    } else {
+        cover_routes.route_layer(middleware::from_fn(require_token))
+    };
+    let router = Router::new()
+        .merge(json_routes)
+        .merge(cover_routes)
         .route(
             "/:album_id/:disc_id/:track_id",
             get(audio_redirect::<P>)
-                .head(annil::route::user::audio_head::<P>),
-        )
-        .route(
-            "/admin/reload",
-            post(annil::route::admin::reload::<P>),
+                .head(audio_head::<P>),
         )
-        .route("/admin/sign", post(annil::route::admin::sign))
-        .layer(
-            cors::CorsLayer::new()
-                .allow_methods([Method::GET, Method::OPTIONS, Method::POST])
-                .allow_headers(cors::Any)
-                .allow_origin(cors::Any),
-        )
-        .layer(ServiceBuilder::new().layer(Extension(initial_state)))
-        .layer(Extension(provider))
-        .layer(Extension(key));
+        .route_layer(middleware::from_fn(unavailable_while_reloading))
+        .layer(Extension(options.reload_status.clone()))
+        .layer(Extension(Arc::new(options.cache_control.clone())))
+        .layer(Extension(ProxyAudio(options.proxy_audio)))
+        .layer(Extension(
+            NonZeroUsize::new(options.webp_cover_entries).map(|n| Arc::new(WebpCovers::new(n))),
+        ))
+        .layer(Extension(MaxAudioBytes(options.max_audio_bytes)))
+        .layer(Extension(MaxBytesPerRequest(options.max_bytes_per_request)))
+        .layer(options.cors.public.layer());
+    // admin routes are merged below, so they are exempt
+    let router = match &options.rate_limit {
+        Some(limit) => router.layer(middleware::from_fn_with_state(
+            Arc::new(RateLimiter::new(limit.clone())),
+            rate_limit,
+        )),
+        None => router,
+    };
+    let router = with_metrics(router, options);
+    let router = if options.with_admin {
+        router.merge(admin_routes::<P>(options))
+    } else {
+        router
+    };
+    // probes don't need cors, so mount them after the cors layer
+    let router = router
+        .route("/healthz", get(healthz::<P>))
+        .layer(Extension(Arc::new(Health::default())));
+    let router = match &options.metrics {
+        Some(handle) => {
+            let handle = handle.clone();
+            router.route("/metrics", get(move || async move { handle.render() }))
+        }
+        None => router,
+    };
+    let router = with_base_path(router, options);
 
-    router
+    // outermost, so that everything logged for a request carries its id
+    let router = router.layer(middleware::from_fn(request_id));
+
+    with_state(router, provider, initial_state, key)
+}
+
+/// Builds a router containing only the admin routes.
+pub fn make_admin_app<P: AnniURLProvider + Send + Sync + 'static>(
+    provider: Arc<AnnilProvider<P>>,
+    initial_state: Arc<AnnilState>,
+    key: Arc<AnnilKeys>,
+    options: &AppOptions,
+) -> Router {
+    let router = with_base_path(admin_routes::<P>(options), options)
+        .layer(middleware::from_fn(request_id));
+    with_state(router, provider, initial_state, key)
 }
@@ -0,0 +1,232 @@
+use std::{fmt::Display, io, net::SocketAddr, str::FromStr};
+
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// Address to serve on, either `ip:port` or `unix:/path/to/socket`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(addr: &str) -> Result<Self, Self::Err> {
+        match addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) if !path.is_empty() => Ok(Self::Unix(path.into())),
+            #[cfg(unix)]
+            Some(_) => Err(String::from("missing socket path after `unix:`")),
+            #[cfg(not(unix))]
+            Some(_) => Err(String::from(
+                "unix sockets are not supported on this platform",
+            )),
+            None => addr
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|e| format!("invalid address `{addr}`: {e}")),
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddr {
+    type Error = String;
+
+    fn try_from(addr: String) -> Result<Self, Self::Error> {
+        addr.parse()
+    }
+}
+
+impl Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => addr.fmt(f),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl ListenAddr {
+    /// Binds the address.
+    ///
+    /// A stale socket file left behind by a previous run is removed first, and `socket_mode`
+    /// sets the permissions of a newly created socket.
+    pub async fn bind(&self, socket_mode: Option<u32>) -> io::Result<Listener> {
+        match self {
+            Self::Tcp(addr) => Ok(Listener::Tcp(bind_tcp(*addr)?)),
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+                match tokio::fs::symlink_metadata(path).await {
+                    Ok(metadata) if metadata.file_type().is_socket() => {
+                        if tokio::net::UnixStream::connect(path).await.is_ok() {
+                            return Err(io::Error::new(
+                                io::ErrorKind::AddrInUse,
+                                format!("{} is in use by another server", path.display()),
+                            ));
+                        }
+                        tracing::info!(path = %path.display(), "removing stale socket");
+                        tokio::fs::remove_file(path).await?;
+                    }
+                    // never delete anything that isn't a socket
+                    Ok(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} exists and is not a socket", path.display()),
+                        ))
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+
+                let listener = tokio::net::UnixListener::bind(path)?;
+                if let Some(mode) = socket_mode {
+                    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+                }
+                Ok(Listener::Unix(listener))
+            }
+        }
+    }
+}
+
+/// Binds a tcp address.
+///
+/// Ipv6 sockets only accept ipv6 connections, so that `0.0.0.0` and `[::]` can be bound to the
+/// same port.
+fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // as done by std, so that restarts don't wait for old connections to time out
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Serves `app` until `shutdown` is cancelled and in-flight requests are done.
+///
+/// Tcp connections are served over https if `tls` is given.
+pub async fn serve(
+    listener: Listener,
+    app: Router,
+    tls: Option<RustlsConfig>,
+    shutdown: CancellationToken,
+) -> io::Result<()> {
+    match (listener, tls) {
+        (Listener::Tcp(listener), None) => {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+            .await
+        }
+        (Listener::Tcp(listener), Some(tls)) => serve_tls(listener, app, tls, shutdown).await,
+        #[cfg(unix)]
+        (Listener::Unix(_), Some(_)) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "tls is not supported on unix sockets",
+        )),
+        #[cfg(unix)]
+        (Listener::Unix(listener), None) => serve_unix(listener, app, shutdown).await,
+    }
+}
+
+/// Serves https with http/2 enabled, which `axum::serve` doesn't do.
+async fn serve_tls(
+    listener: TcpListener,
+    app: Router,
+    tls: RustlsConfig,
+    shutdown: CancellationToken,
+) -> io::Result<()> {
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown.cancelled().await;
+            // the caller bounds how long draining may take
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    axum_server::from_tcp_rustls(listener.into_std()?, tls)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
+
+/// Serves connections of a unix socket, which `axum::serve` only does for tcp.
+#[cfg(unix)]
+async fn serve_unix(
+    listener: tokio::net::UnixListener,
+    app: Router,
+    shutdown: CancellationToken,
+) -> io::Result<()> {
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+        service::TowerToHyperService,
+    };
+    use tokio_util::task::TaskTracker;
+
+    let connections = TaskTracker::new();
+    loop {
+        let result = tokio::select! {
+            result = listener.accept() => result,
+            _ = shutdown.cancelled() => break,
+        };
+        // errors like running out of file descriptors pass, so keep serving as `axum::serve` does
+        let socket = match result {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to accept unix socket connection");
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let conn = builder.serve_connection_with_upgrades(TokioIo::new(socket), service);
+            tokio::pin!(conn);
+
+            let result = tokio::select! {
+                result = conn.as_mut() => result,
+                _ = shutdown.cancelled() => {
+                    conn.as_mut().graceful_shutdown();
+                    conn.await
+                }
+            };
+            if let Err(e) = result {
+                tracing::debug!(error = %e, "unix socket connection failed");
+            }
+        });
+    }
+
+    connections.close();
+    connections.wait().await;
+    Ok(())
+}
@@ -1,28 +1,50 @@
-use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+mod config;
+mod listen;
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
 use annil::{provider::AnnilProvider, state::AnnilKeys};
-use annil_server::{make_app, make_state, provider::SeafileProvider};
+use annil_server::{
+    load_metadata, make_admin_app, make_app, make_state,
+    provider::{
+        AlbumListCache, AnniURLProvider, AnyProvider, ConcurrencyLimit, CoverCache,
+        FallbackProvider, FilteredProvider, ManifestProvider, UpstreamLimit,
+    },
+    spawn_prewarm_task, spawn_reload_task, AlbumSnapshot, AppOptions, ReloadStatus,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use config::{Config, MetadataSource, ProviderConfig, ProxyConfig};
+use futures_util::future::try_join_all;
+use metrics_exporter_prometheus::PrometheusBuilder;
 use reqwest_dav::re_exports::reqwest;
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::EnvFilter;
 
-#[derive(serde::Deserialize)]
-struct SeafileConfig {
-    token: String,
-    base: String,
-    repo_id: String,
-}
-
-#[derive(serde::Deserialize)]
-struct Config {
-    listen: SocketAddr,
-    sign_key: String,
-    share_key: String,
-    admin_token: String,
-
-    provider: SeafileConfig,
+/// Builds the client shared by all requests to a provider, so that they share one connection pool.
+///
+/// Webdav requests go through this client as well, so they carry the same `User-Agent`.
+fn build_client(config: &Config, provider: &ProviderConfig) -> reqwest::Result<reqwest::Client> {
+    let builder = reqwest::Client::builder()
+        .user_agent(&config.user_agent)
+        .timeout(Duration::from_secs(config.request_timeout_secs))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .tcp_keepalive(Duration::from_secs(config.tcp_keepalive_secs));
+    let builder = match provider.proxy() {
+        Some(ProxyConfig::None) => builder.no_proxy(),
+        Some(ProxyConfig::All(proxy)) => builder.proxy(proxy.clone()),
+        None => builder,
+    };
+    builder.build()
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
     let app = clap::Command::new("AnnilServer").arg(
         clap::arg!(-c --config <FILE> "path to config file")
             .required(true)
@@ -35,31 +57,238 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .get_one::<PathBuf>("config")
         .expect("config file required");
 
-    let config: Config = toml::from_str(&std::fs::read_to_string(config_file)?)?;
+    let mut config: Config = toml::from_str(&std::fs::read_to_string(config_file)?)?;
+    let mut errors: Vec<_> = [config.resolve_secrets(), config.validate()]
+        .into_iter()
+        .filter_map(Result::err)
+        .flatten()
+        .collect();
+    // annil serves album info from the repository, so starting without it is a config error
+    if let Some(metadata) = config.metadata.as_ref().filter(|_| errors.is_empty()) {
+        if let Err(e) = load_metadata(&metadata.build()).await {
+            errors.push(format!("`metadata` can't be loaded: {e}"));
+        }
+    }
+    if !errors.is_empty() {
+        eprintln!("invalid config file {}:", config_file.display());
+        for error in errors {
+            eprintln!("  - {error}");
+        }
+        std::process::exit(2);
+    }
+
+    let client = build_client(&config, &config.provider)?;
 
-    let provider = Arc::new(AnnilProvider::new(SeafileProvider::new(
-        reqwest::Client::new(),
-        config.provider.token,
-        config.provider.base,
-        config.provider.repo_id,
-    )));
+    let provider = config.provider.build(client, config.probe_duration)?;
+    serve(&config, wrap_provider(&config, provider)?).await
+}
+
+/// Wraps the provider in every layer, disabled ones passing requests straight through, so that
+/// the server is built around a single provider type.
+fn wrap_provider(
+    config: &Config,
+    provider: AnyProvider,
+) -> Result<impl AnniURLProvider + Send + Sync + 'static, Box<dyn std::error::Error>> {
+    let limit = config
+        .provider
+        .upstream_limit()
+        .unwrap_or_else(UpstreamLimit::unlimited);
+    let provider = ConcurrencyLimit::new(provider, limit);
+
+    let fallback = match &config.fallback {
+        Some(fallback) => {
+            let client = build_client(config, fallback)?;
+            Some(fallback.build(client, config.probe_duration)?)
+        }
+        None => None,
+    };
+    let provider = FallbackProvider::new(provider, fallback);
+
+    let provider = match &config.filter {
+        Some(filter) => FilteredProvider::new(provider, filter.mode, filter.path.clone())?,
+        None => FilteredProvider::unfiltered(provider),
+    };
+    let provider = match &config.manifest {
+        Some(path) => ManifestProvider::new(provider, path.clone())?,
+        None => ManifestProvider::without_manifest(provider),
+    };
+    let provider = AlbumListCache::new(provider, Duration::from_secs(config.album_cache_secs));
+    Ok(CoverCache::new(
+        provider,
+        config.cover_cache_bytes,
+        Duration::from_secs(config.cover_cache_link_secs),
+    ))
+}
+
+async fn serve<P: AnniURLProvider + Send + Sync + 'static>(
+    config: &Config,
+    provider: P,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let provider = Arc::new(AnnilProvider::new(provider));
 
     let initial_state = Arc::new(
         make_state(
             String::from(concat!("AnnilServer v", env!("CARGO_PKG_VERSION"))),
             &provider,
+            config.metadata.as_ref().map(MetadataSource::build),
         )
         .await,
     );
 
+    let albums = Arc::new(AlbumSnapshot::default());
+    let reload_status = Arc::new(ReloadStatus::default());
+    if let Err(e) = albums.capture(&provider).await {
+        tracing::warn!(error = %e, "failed to snapshot albums");
+    }
+
+    if config.prewarm_covers {
+        spawn_prewarm_task(
+            provider.clone(),
+            albums.clone(),
+            Duration::from_millis(config.prewarm_interval_ms),
+        );
+    }
+
+    if let Some(interval) = config.reload_interval_secs {
+        spawn_reload_task(
+            provider.clone(),
+            initial_state.clone(),
+            albums.clone(),
+            reload_status.clone(),
+            Duration::from_secs(interval),
+            Duration::from_secs(config.reload_etag_timeout_secs),
+        );
+    }
+
     let key = Arc::new(AnnilKeys::new(
         config.sign_key.as_bytes(),
         config.share_key.as_bytes(),
-        config.admin_token,
+        config.admin_token.clone(),
     ));
 
-    let listener = tokio::net::TcpListener::bind(config.listen).await?;
-    axum::serve(listener, make_app(provider, initial_state, key))
-        .await
-        .map_err(Into::into)
+    let mut listeners = Vec::with_capacity(config.listen.len());
+    for addr in &config.listen {
+        listeners.push(addr.bind(config.socket_mode).await?);
+        tracing::info!(%addr, "listening");
+    }
+    let metrics = if config.metrics {
+        let buckets = [
+            0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+        ];
+        Some(
+            PrometheusBuilder::new()
+                .set_buckets(&buckets)?
+                .install_recorder()?,
+        )
+    } else {
+        None
+    };
+    let options = AppOptions {
+        cors: config.cors.clone(),
+        with_admin: config.admin_listen.is_none(),
+        metrics,
+        albums,
+        reload_status,
+        rate_limit: config.rate_limit.clone(),
+        without_sign: !config.admin.enable_sign,
+        etag_timeout: Duration::from_secs(config.reload_etag_timeout_secs),
+        public_stats: config.admin.public_stats,
+        public_covers: config.admin.public_covers,
+        webp_cover_entries: config.webp_cover_entries,
+        proxy_audio: config.proxy_when_private,
+        max_audio_bytes: config.max_audio_bytes,
+        max_bytes_per_request: config.max_bytes_per_request,
+        base_path: config.base_path.clone(),
+        cache_control: config.cache_control.clone(),
+    };
+
+    let admin_listener = match &config.admin_listen {
+        Some(addr) => {
+            let listener = addr.bind(config.socket_mode).await?;
+            tracing::info!(%addr, "listening for admin requests");
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let (app, admin) = match admin_listener {
+        None => (make_app(provider, initial_state, key, &options), None),
+        Some(admin_listener) => (
+            make_app(
+                provider.clone(),
+                initial_state.clone(),
+                key.clone(),
+                &options,
+            ),
+            Some((
+                admin_listener,
+                make_admin_app(provider, initial_state, key, &options),
+            )),
+        ),
+    };
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("shutting down, draining connections");
+            shutdown.cancel();
+        }
+    });
+
+    let tls = match &config.tls {
+        Some(tls) => Some(RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path).await?),
+        None => None,
+    };
+    let public = try_join_all(
+        listeners
+            .into_iter()
+            .map(|listener| listen::serve(listener, app.clone(), tls.clone(), shutdown.clone())),
+    );
+    let admin = async {
+        match admin {
+            Some((listener, app)) => listen::serve(listener, app, tls, shutdown.clone()).await,
+            None => Ok(()),
+        }
+    };
+    let drain_timeout = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(Duration::from_secs(config.shutdown_timeout_secs)).await;
+    };
+
+    tokio::select! {
+        result = async { tokio::try_join!(public, admin) } => {
+            result?;
+        }
+        _ = drain_timeout => {
+            tracing::warn!("timed out draining connections");
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves once SIGINT or SIGTERM is received.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
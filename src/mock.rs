@@ -0,0 +1,194 @@
+//! An in-memory provider, for exercising the routes without an upstream.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    num::NonZeroU8,
+};
+
+use anni_provider::{
+    AnniProvider, AudioInfo, AudioResourceReader, ProviderError, Range, ResourceReader,
+};
+use axum::body::Bytes;
+
+use crate::provider::{AnniURLProvider, AudioDetails, RangeNotSatisfiable};
+
+/// Serves tracks and covers held in memory.
+///
+/// Audio is linked to under the base set with [`MockProvider::with_links`], and streamed
+/// otherwise. Covers are always streamed. Ranges are answered like the upstreams of real
+/// providers answer them: the range sent is reported out of the whole file, and ranges starting
+/// beyond the end are rejected.
+#[derive(Default)]
+pub struct MockProvider {
+    tracks: HashMap<(String, NonZeroU8, NonZeroU8), Bytes>,
+    covers: HashMap<(String, Option<NonZeroU8>), Bytes>,
+    link_base: Option<String>,
+    failing: bool,
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a track, served as FLAC whatever `audio` holds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `disc_id` or `track_id` is 0.
+    pub fn with_track(
+        mut self,
+        album_id: &str,
+        disc_id: u8,
+        track_id: u8,
+        audio: impl Into<Bytes>,
+    ) -> Self {
+        let disc_id = NonZeroU8::new(disc_id).expect("disc ids start at 1");
+        let track_id = NonZeroU8::new(track_id).expect("track ids start at 1");
+        self.tracks
+            .insert((album_id.to_owned(), disc_id, track_id), audio.into());
+        self
+    }
+
+    /// Adds the cover of an album, or of one of its discs.
+    pub fn with_cover(
+        mut self,
+        album_id: &str,
+        disc_id: Option<u8>,
+        cover: impl Into<Bytes>,
+    ) -> Self {
+        let disc_id = disc_id.map(|id| NonZeroU8::new(id).expect("disc ids start at 1"));
+        self.covers
+            .insert((album_id.to_owned(), disc_id), cover.into());
+        self
+    }
+
+    /// Links to audio at `{base}/{album_id}/{disc_id}/{track_id}` instead of streaming it.
+    pub fn with_links(mut self, base: impl Into<String>) -> Self {
+        self.link_base = Some(base.into());
+        self
+    }
+
+    /// Fails every request for audio or covers, as an unreachable upstream would.
+    pub fn with_failure(mut self) -> Self {
+        self.failing = true;
+        self
+    }
+
+    fn track(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<&Bytes> {
+        if self.failing {
+            return Err(ProviderError::GeneralError);
+        }
+        self.tracks
+            .get(&(album_id.to_owned(), disc_id, track_id))
+            .ok_or(ProviderError::FileNotFound)
+    }
+}
+
+fn audio_info(audio: &Bytes) -> AudioInfo {
+    AudioInfo {
+        extension: String::from("flac"),
+        size: audio.len(),
+        duration: 0,
+    }
+}
+
+#[async_trait::async_trait]
+impl AnniProvider for MockProvider {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        Ok(self
+            .tracks
+            .keys()
+            .map(|(album_id, _, _)| Cow::Borrowed(album_id.as_str()))
+            .collect())
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        let audio = self.track(album_id, disc_id, track_id)?;
+        let size = audio.len() as u64;
+        // rejected like an upstream answering 416 would be
+        if range.start > 0 && range.start >= size {
+            return Err(std::io::Error::other(RangeNotSatisfiable { size }).into());
+        }
+
+        let end = range
+            .end
+            .map_or(size, |end| end.saturating_add(1).min(size));
+        let reader: ResourceReader =
+            Box::pin(Cursor::new(audio.slice(range.start as usize..end as usize)));
+        Ok(AudioResourceReader {
+            info: audio_info(audio),
+            range: Range {
+                start: range.start,
+                end: Some(end.saturating_sub(1)),
+                total: Some(size),
+            },
+            reader,
+        })
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        if self.failing {
+            return Err(ProviderError::GeneralError);
+        }
+        let cover = self
+            .covers
+            .get(&(album_id.to_owned(), disc_id))
+            .ok_or(ProviderError::FileNotFound)?;
+        Ok(Box::pin(Cursor::new(cover.clone())))
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        Ok(())
+    }
+}
+
+impl AnniURLProvider for MockProvider {
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        match &self.link_base {
+            Some(base) => {
+                self.track(album_id, disc_id, track_id)?;
+                Ok(Ok(format!("{base}/{album_id}/{disc_id}/{track_id}")))
+            }
+            None => Ok(Err(self
+                .get_audio(album_id, disc_id, track_id, range)
+                .await?)),
+        }
+    }
+
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        let audio = self.track(album_id, disc_id, track_id)?;
+        Ok(AudioDetails {
+            info: audio_info(audio),
+            stream: None,
+        })
+    }
+}
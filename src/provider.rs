@@ -1,43 +1,124 @@
-use std::{borrow::Cow, collections::HashSet, fmt::Display, future::Future, io::Cursor, num::NonZeroU8};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    future::Future,
+    io::{Cursor, SeekFrom},
+    num::{NonZeroU8, NonZeroUsize},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
 
 use anni_flac::{
     blocks::BlockStreamInfo,
     prelude::{AsyncDecode, Encode},
 };
-use anni_provider::{AnniProvider, AudioInfo, AudioResourceReader, Range, ResourceReader};
-use axum::http::{
-    header::{AUTHORIZATION, CONTENT_RANGE, RANGE},
-    Method,
+use anni_provider::{
+    AnniProvider, AudioInfo, AudioResourceReader, ProviderError, Range, ResourceReader,
+};
+use axum::{
+    body::Bytes,
+    http::{
+        header::{AUTHORIZATION, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, RANGE},
+        Method, StatusCode,
+    },
 };
 use futures_util::StreamExt;
+use lru::LruCache;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::Rng;
 use reqwest_dav::{
     re_exports::reqwest::{self, Response},
     Auth, Client,
 };
-use serde::Deserialize;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use rusty_s3::{actions::ListObjectsV2, Bucket, Credentials, S3Action};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
 use tokio_util::io::StreamReader;
 
 pub struct WebdavProvider {
     client: Client,
+    paths: Arc<dyn PathMapper>,
+    retry: Retry,
+    /// album ids, listed on first use and refreshed on reload
+    albums: RwLock<Option<HashSet<String>>>,
+    /// album ids listed by `prepare_reload`, swapped in by the following `reload`
+    prepared: Mutex<Option<HashSet<String>>>,
+    /// cover file names to look for, in order of preference
+    cover_names: Vec<String>,
+    /// audio file extensions appended to track paths, in order of preference
+    extensions: Vec<String>,
+    /// read the duration of FLAC audio from its header while streaming it
+    probe_duration: bool,
+    /// put basic auth credentials into links handed to clients
+    credentials_in_links: bool,
 }
 
 impl WebdavProvider {
-    pub fn new(host: String, auth: Auth) -> Self {
+    pub fn new(
+        client: reqwest::Client,
+        host: String,
+        auth: Auth,
+        paths: Arc<dyn PathMapper>,
+        retry: Retry,
+    ) -> Self {
         Self {
             client: Client {
-                agent: reqwest::Client::new(),
+                agent: client,
                 host,
                 auth,
+                // digest auth fills this in from the first challenge of the server
                 digest_auth: Default::default(),
             },
+            paths,
+            retry,
+            albums: Default::default(),
+            prepared: Default::default(),
+            cover_names: vec![String::from("cover.jpg")],
+            extensions: Vec::new(),
+            probe_duration: true,
+            credentials_in_links: false,
         }
     }
-}
 
-#[async_trait::async_trait]
-impl AnniProvider for WebdavProvider {
-    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+    /// Reports a duration of 0 instead of reading it from the header of FLAC audio.
+    pub fn with_probe_duration(mut self, probe_duration: bool) -> Self {
+        self.probe_duration = probe_duration;
+        self
+    }
+
+    pub fn with_cover_names(mut self, cover_names: Vec<String>) -> Self {
+        self.cover_names = cover_names;
+        self
+    }
+
+    /// Looks for tracks at their paths with each of `extensions` appended, in the listing of their
+    /// directory.
+    ///
+    /// Without extensions, tracks are at their paths as they are, and taken to be FLAC unless the
+    /// file name has an extension.
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Redirects clients to links carrying the basic auth username and password, instead of
+    /// streaming audio from servers that need them.
+    ///
+    /// Anyone the links are handed to learns the credentials, and they end up in browser
+    /// histories and logs.
+    pub fn with_credentials_in_links(mut self, credentials_in_links: bool) -> Self {
+        self.credentials_in_links = credentials_in_links;
+        self
+    }
+
+    async fn list_albums(&self) -> anni_provider::Result<HashSet<String>> {
         Ok(self
             .client
             .list_rsp("/", reqwest_dav::Depth::Number(1))
@@ -49,11 +130,87 @@ impl AnniProvider for WebdavProvider {
                     .href
                     .trim_end_matches('/')
                     .rsplit_once('/')
-                    .map(|(_, album_id)| Cow::Owned(album_id.to_owned()))
+                    .map(|(_, album_id)| album_id.to_owned())
+            })
+            .collect())
+    }
+
+    /// Lists the names of files in a directory.
+    async fn list_files(&self, dir: String) -> anni_provider::Result<Vec<String>> {
+        Ok(self
+            .client
+            .list_rsp(&dir, reqwest_dav::Depth::Number(1))
+            .await
+            .map_err(handle_dav_error)?
+            .into_iter()
+            // directories, including the listed one itself, end with a slash
+            .filter(|entry| !entry.href.ends_with('/'))
+            .filter_map(|entry| {
+                let (_, name) = entry.href.rsplit_once('/')?;
+                Some(percent_decode_str(name).decode_utf8_lossy().into_owned())
             })
             .collect())
     }
 
+    /// Finds the file of a track, returning its path and extension.
+    async fn find_track(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<(String, String)> {
+        let track = self.paths.track_path(album_id, disc_id, track_id);
+        if self.extensions.is_empty() {
+            let extension = extension_of(&track).to_owned();
+            return Ok((track, extension));
+        }
+        let (dir, name) = track.rsplit_once('/').unwrap_or(("", track.as_str()));
+        let files = self.list_files(dir.to_owned()).await?;
+        self.extensions
+            .iter()
+            .find(|extension| files.contains(&format!("{name}.{extension}")))
+            .map(|extension| (format!("{track}.{extension}"), extension.clone()))
+            .ok_or(ProviderError::FileNotFound)
+    }
+
+    /// Builds a url clients can fetch `path` from directly.
+    ///
+    /// Returns `None` if the server needs auth, unless basic auth credentials may be put into
+    /// links.
+    fn direct_url(&self, path: &str) -> Option<String> {
+        let mut url = reqwest::Url::parse(&format!(
+            "{}/{path}",
+            self.client.host.trim_end_matches('/')
+        ))
+        .ok()?;
+        match &self.client.auth {
+            Auth::Anonymous => {}
+            Auth::Basic(username, password) if self.credentials_in_links => {
+                url.set_username(username).ok()?;
+                url.set_password(Some(password)).ok()?;
+            }
+            // digest auth needs a challenge round-trip, so only basic auth can be linked to
+            _ => return None,
+        }
+        Some(url.into())
+    }
+}
+
+#[async_trait::async_trait]
+impl AnniProvider for WebdavProvider {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        let cached = self.albums.read().unwrap().clone();
+        let albums = match cached {
+            Some(albums) => albums,
+            None => {
+                let albums = self.list_albums().await?;
+                *self.albums.write().unwrap() = Some(albums.clone());
+                albums
+            }
+        };
+        Ok(albums.into_iter().map(Cow::Owned).collect())
+    }
+
     async fn get_audio(
         &self,
         album_id: &str,
@@ -61,48 +218,271 @@ impl AnniProvider for WebdavProvider {
         track_id: NonZeroU8,
         range: Range,
     ) -> anni_provider::Result<AudioResourceReader> {
-        let path = format!("{album_id}/{disc_id}/{track_id}");
-        let req = self
-            .client
-            .start_request(Method::GET, &path)
-            .await
-            .map_err(handle_dav_error)?;
-        let req = match range.to_range_header() {
-            Some(h) => req.header(RANGE, h),
-            None => req,
-        };
-        let resp = req.send().await?;
-        let content_length = resp.content_length().unwrap();
-        let (duration, reader) = read_response(resp).await?;
-        Ok(AudioResourceReader {
-            info: AudioInfo {
-                extension: String::from("flac"),
-                size: content_length as usize,
-                duration,
-            },
-            range,
-            reader,
-        })
+        let streamed = self
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(streamed.audio)
     }
 
     async fn get_cover(
         &self,
-        _album_id: &str,
-        _disc_id: Option<NonZeroU8>,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
     ) -> anni_provider::Result<ResourceReader> {
-        todo!()
+        Ok(self.fetch_cover(album_id, disc_id).await?.0)
     }
 
     async fn reload(&mut self) -> anni_provider::Result<()> {
+        let albums = match self.prepared.get_mut().unwrap().take() {
+            Some(albums) => albums,
+            None => self.list_albums().await?,
+        };
+        *self.albums.get_mut().unwrap() = Some(albums);
         Ok(())
     }
 }
 
+impl WebdavProvider {
+    /// Fetches `range` of the track at `path` through the webdav client.
+    async fn fetch_track(
+        &self,
+        path: &str,
+        extension: &str,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        let req = self
+            .client
+            .start_request(Method::GET, path)
+            .await
+            .map_err(handle_dav_error)?;
+        fetch_audio(req, extension, range, &self.retry, self.probe_duration).await
+    }
+
+    /// Fetches the first cover that exists, along with the validators the server sent with it.
+    async fn fetch_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<(ResourceReader, Validators)> {
+        let disc_dir = self
+            .paths
+            .disc_dir(album_id, disc_id.unwrap_or(NonZeroU8::MIN));
+        for name in &self.cover_names {
+            let resp = self
+                .client
+                .start_request(Method::GET, &format!("{disc_dir}/{name}"))
+                .await
+                .map_err(handle_dav_error)?
+                .send()
+                .await?;
+            if resp.status() == StatusCode::NOT_FOUND {
+                continue;
+            }
+            let resp = resp.error_for_status()?;
+            let validators = Validators::of(&resp);
+            return Ok((read_body(resp), validators));
+        }
+        Err(ProviderError::FileNotFound)
+    }
+}
+
+impl AnniURLProvider for WebdavProvider {
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        let albums = self.list_albums().await?;
+        *self.prepared.lock().unwrap() = Some(albums.clone());
+        Ok(albums)
+    }
+
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        self.fetch_cover(album_id, disc_id).await.map(Err)
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        let (path, extension) = self.find_track(album_id, disc_id, track_id).await?;
+        self.fetch_track(&path, &extension, range).await
+    }
+
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        let (path, _) = self.find_track(album_id, disc_id, track_id).await?;
+        match self.direct_url(&path) {
+            Some(url) => Ok(Ok(url)),
+            None => self
+                .get_audio(album_id, disc_id, track_id, range)
+                .await
+                .map(Result::Err),
+        }
+    }
+
+    /// Reads the details through the link, which also finds out whether the track exists.
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        let (path, extension) = self.find_track(album_id, disc_id, track_id).await?;
+        match self.direct_url(&path) {
+            Some(url) => {
+                let details =
+                    fetch_details(&self.client.agent, &url, &extension, &self.retry).await?;
+                Ok(Ok((url, details)))
+            }
+            None => self
+                .fetch_track(&path, &extension, range)
+                .await
+                .map(Result::Err),
+        }
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        // tracks with extensions are found in the listing of their directory
+        let path = match self.find_track(album_id, disc_id, track_id).await {
+            Ok((_, _)) if !self.extensions.is_empty() => return Ok(true),
+            Ok((path, _)) => path,
+            Err(ProviderError::FileNotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        match self
+            .client
+            .list_rsp(&path, reqwest_dav::Depth::Number(0))
+            .await
+            .map_err(handle_dav_error)
+        {
+            Ok(_) => Ok(true),
+            Err(ProviderError::FileNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        probe_discs(&*self.paths, album_id, |dir| async move {
+            let files = self.list_files(dir).await?;
+            if self.extensions.is_empty() {
+                return Ok(files);
+            }
+            Ok(files
+                .iter()
+                .filter_map(|name| strip_extension(name, &self.extensions))
+                .map(String::from)
+                .collect())
+        })
+        .await
+        .map(Some)
+    }
+}
+
 pub struct SeafileProvider {
     client: reqwest::Client,
-    token: String,
+    /// replaced when it expires, if `credentials` are set
+    token: RwLock<String>,
+    credentials: Option<SeafileCredentials>,
+    /// held while a new token is fetched, so that it is only fetched once
+    refreshing: tokio::sync::Mutex<()>,
     base: String,
-    repo_id: String,
+    repo_ids: Vec<String>,
+    /// album id -> repo id, rebuilt every time albums are listed
+    album_repos: RwLock<HashMap<String, String>>,
+    /// album repos listed by `prepare_reload`, swapped in by the following `reload`
+    prepared: Mutex<Option<HashMap<String, String>>>,
+    links: PathCache<String>,
+    /// names of the files in a directory, by its path, which tracks and covers are looked up in
+    listings: PathCache<Arc<HashSet<String>>>,
+    retry: Retry,
+    /// layout of tracks, without the extension
+    paths: Arc<dyn PathMapper>,
+    /// audio file extensions to probe, in order of preference
+    extensions: Vec<String>,
+    parallel: Option<ParallelFetch>,
+    /// upstream requests taking longer than this are logged
+    slow_request: Option<Duration>,
+    /// cover file names to look for, in order of preference
+    cover_names: Vec<String>,
+    /// read the duration of FLAC audio from its header while streaming it
+    probe_duration: bool,
+}
+
+type CachedEntry<V> = Arc<tokio::sync::Mutex<Option<(V, Instant)>>>;
+
+/// Caches what is fetched for a path, such as its download link, up to [`MAX_CACHED_PATHS`]
+/// paths.
+///
+/// Every path has its own lock, so concurrent lookups of an uncached path fetch it only once.
+struct PathCache<V> {
+    ttl: Duration,
+    entries: Mutex<LruCache<String, CachedEntry<V>>>,
+}
+
+/// Paths cached at most, beyond which the least recently used ones are evicted.
+const MAX_CACHED_PATHS: usize = 4096;
+
+impl<V: Clone> PathCache<V> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_CACHED_PATHS).unwrap())),
+        }
+    }
+
+    async fn get_or_fetch<F, Fut>(&self, path: String, fetch: F) -> anni_provider::Result<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anni_provider::Result<V>>,
+    {
+        if self.ttl.is_zero() {
+            return fetch().await;
+        }
+
+        let entry = self
+            .entries
+            .lock()
+            .unwrap()
+            .get_or_insert(path, Default::default)
+            .clone();
+        let mut entry = entry.lock().await;
+        if let Some((value, fetched_at)) = &*entry {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(value.clone());
+            }
+        }
+
+        let value = fetch().await?;
+        *entry = Some((value.clone(), Instant::now()));
+        Ok(value)
+    }
+
+    fn clear(&mut self) {
+        self.entries.get_mut().unwrap().clear();
+    }
+}
+
+/// Body of a download link response, which is an error object instead of a url on failure.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DownloadLink {
+    Link(String),
+    Error { error_msg: String },
 }
 
 #[derive(Deserialize)]
@@ -110,24 +490,155 @@ struct DirectoryItem {
     pub name: String,
 }
 
+/// Account used to fetch a new api token when the current one is rejected.
+pub struct SeafileCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+struct AuthToken {
+    token: String,
+}
+
 impl SeafileProvider {
-    pub fn new(client: reqwest::Client, token: String, base: String, repo_id: String) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        client: reqwest::Client,
+        token: String,
+        base: String,
+        repo_ids: Vec<String>,
+        link_cache_ttl: Duration,
+        listing_cache_ttl: Duration,
+        retry: Retry,
+        paths: Arc<dyn PathMapper>,
+        extensions: Vec<String>,
+    ) -> Self {
         Self {
             client,
-            token,
+            token: RwLock::new(token),
+            credentials: None,
+            refreshing: Default::default(),
             base,
-            repo_id,
+            repo_ids,
+            album_repos: Default::default(),
+            prepared: Default::default(),
+            links: PathCache::new(link_cache_ttl),
+            listings: PathCache::new(listing_cache_ttl),
+            retry,
+            paths,
+            extensions,
+            parallel: None,
+            slow_request: None,
+            cover_names: ["cover.jpg", "folder.jpg"].map(String::from).to_vec(),
+            probe_duration: true,
         }
     }
 
-    pub async fn list_albums(&self) -> reqwest::Result<Vec<String>> {
-        let url = format!("{}/api2/repos/{}/dir/?t=d", self.base, self.repo_id);
-        Ok(self
+    /// Reports a duration of 0 instead of reading it from the header of FLAC audio.
+    pub fn with_probe_duration(mut self, probe_duration: bool) -> Self {
+        self.probe_duration = probe_duration;
+        self
+    }
+
+    pub fn with_cover_names(mut self, cover_names: Vec<String>) -> Self {
+        self.cover_names = cover_names;
+        self
+    }
+
+    pub fn with_credentials(mut self, credentials: SeafileCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn with_parallel_fetch(mut self, parallel: ParallelFetch) -> Self {
+        self.parallel = Some(parallel);
+        self
+    }
+
+    pub fn with_slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_request = Some(threshold);
+        self
+    }
+
+    /// Logs a request started at `start` if it took longer than the configured threshold.
+    fn warn_if_slow(&self, operation: &str, path: &str, start: Instant) {
+        let elapsed = start.elapsed();
+        if self
+            .slow_request
+            .is_some_and(|threshold| elapsed > threshold)
+        {
+            tracing::warn!(
+                operation,
+                path,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "slow upstream request"
+            );
+        }
+    }
+
+    /// Sends a GET request to the api, authorized with the current token.
+    ///
+    /// If the token is rejected and credentials are set, a new token is fetched and the request
+    /// is sent once more.
+    async fn api_get(&self, url: &str) -> reqwest::Result<Response> {
+        let token = self.token.read().unwrap().clone();
+        let req = self
+            .client
+            .get(url)
+            .header(AUTHORIZATION, format!("Token {token}"));
+        let resp = self.retry.send(req).await?;
+        let Some(credentials) = &self.credentials else {
+            return Ok(resp);
+        };
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        let token = self.refresh_token(&token, credentials).await?;
+        let req = self
             .client
             .get(url)
-            .header(AUTHORIZATION, format!("Token {}", self.token))
+            .header(AUTHORIZATION, format!("Token {token}"));
+        self.retry.send(req).await
+    }
+
+    /// Fetches a new token to replace `rejected`.
+    ///
+    /// Requests rejected at the same time share one new token.
+    async fn refresh_token(
+        &self,
+        rejected: &str,
+        credentials: &SeafileCredentials,
+    ) -> reqwest::Result<String> {
+        let _refreshing = self.refreshing.lock().await;
+        let current = self.token.read().unwrap().clone();
+        if current != rejected {
+            return Ok(current);
+        }
+
+        let AuthToken { token } = self
+            .client
+            .post(format!("{}/api2/auth-token/", self.base))
+            .form(&[
+                ("username", &credentials.username),
+                ("password", &credentials.password),
+            ])
             .send()
             .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        tracing::info!("fetched a new seafile token");
+        *self.token.write().unwrap() = token.clone();
+        Ok(token)
+    }
+
+    pub async fn list_repo_albums(&self, repo_id: &str) -> reqwest::Result<Vec<String>> {
+        let url = format!("{}/api2/repos/{}/dir/?t=d", self.base, repo_id);
+        Ok(self
+            .api_get(&url)
+            .await?
             .json::<Vec<DirectoryItem>>()
             .await?
             .into_iter()
@@ -135,21 +646,218 @@ impl SeafileProvider {
             .collect())
     }
 
-    pub async fn get_download_link(&self, path: impl Display) -> reqwest::Result<String> {
+    /// Lists albums across all repos.
+    ///
+    /// If an album appears in more than one repo, the repo listed first in `repo_ids` wins.
+    pub async fn list_albums(&self) -> reqwest::Result<Vec<String>> {
+        let album_repos = self.fetch_album_repos().await?;
+        let albums = album_repos.keys().cloned().collect();
+        *self.album_repos.write().unwrap() = album_repos;
+        Ok(albums)
+    }
+
+    /// Maps every album to the repo holding it.
+    async fn fetch_album_repos(&self) -> reqwest::Result<HashMap<String, String>> {
+        let mut album_repos = HashMap::new();
+        for repo_id in &self.repo_ids {
+            for album_id in self.list_repo_albums(repo_id).await? {
+                album_repos
+                    .entry(album_id)
+                    .or_insert_with(|| repo_id.clone());
+            }
+        }
+        Ok(album_repos)
+    }
+
+    async fn repo_of(&self, album_id: &str) -> anni_provider::Result<String> {
+        let cached = self.album_repos.read().unwrap().get(album_id).cloned();
+        if let Some(repo_id) = cached {
+            return Ok(repo_id);
+        }
+
+        // the album may have been added after the last listing
+        self.list_albums().await?;
+        let found = self.album_repos.read().unwrap().get(album_id).cloned();
+        found.ok_or(ProviderError::FileNotFound)
+    }
+
+    /// Finds the file of a track, trying each configured extension in order.
+    ///
+    /// Returns the path of the track and its extension. Seafile hands out download links for
+    /// files that don't exist, so the track is looked for in the listing of its directory even
+    /// with a single extension.
+    async fn find_track(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<(String, String)> {
+        self.locate_track(album_id, disc_id, track_id)
+            .await?
+            .ok_or(ProviderError::FileNotFound)
+    }
+
+    /// Looks for the file of a track in the listing of its directory.
+    async fn locate_track(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<Option<(String, String)>> {
+        let track = self.paths.track_path(album_id, disc_id, track_id);
+        let (dir, name) = track.rsplit_once('/').unwrap_or(("", track.as_str()));
+        let files = self.dir_files(album_id, dir).await?;
+        Ok(self
+            .extensions
+            .iter()
+            .find(|extension| files.contains(&format!("{name}.{extension}")))
+            .map(|extension| (format!("{track}.{extension}"), extension.clone())))
+    }
+
+    /// Names of the files in a directory of an album, listed once per listing cache period.
+    ///
+    /// A missing directory has no files.
+    async fn dir_files(
+        &self,
+        album_id: &str,
+        dir: &str,
+    ) -> anni_provider::Result<Arc<HashSet<String>>> {
+        self.listings
+            .get_or_fetch(dir.to_owned(), || async {
+                match self.list_files(album_id, dir.to_owned()).await {
+                    Ok(files) => Ok(Arc::new(files.into_iter().collect())),
+                    Err(ProviderError::FileNotFound) => Ok(Default::default()),
+                    Err(e) => Err(e),
+                }
+            })
+            .await
+    }
+
+    /// Lists the names of files in a directory of an album.
+    async fn list_files(&self, album_id: &str, dir: String) -> anni_provider::Result<Vec<String>> {
         let url = format!(
-            "{server}/api2/repos/{repo_id}/file/?p={path}&reuse=1",
+            "{server}/api2/repos/{repo_id}/dir/?p={dir}&t=f",
             server = self.base,
-            repo_id = self.repo_id,
+            repo_id = self.repo_of(album_id).await?,
+            dir = utf8_percent_encode(&format!("/{dir}"), NON_ALPHANUMERIC),
         );
-
-        self.client
-            .get(url)
-            .header(AUTHORIZATION, format!("Token {}", self.token))
-            .send()
+        let resp = self.api_get(&url).await?;
+        if resp.status() == StatusCode::NOT_FOUND && !is_html(&resp) {
+            return Err(ProviderError::FileNotFound);
+        }
+        Ok(resp
+            .error_for_status()?
+            .json::<Vec<DirectoryItem>>()
             .await?
-            .json()
+            .into_iter()
+            .map(|file| file.name)
+            .collect())
+    }
+
+    /// Finds the first of the configured cover names that exists, looking for each in the disc
+    /// directory before the album directory.
+    async fn find_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<String> {
+        let disc_dir = self
+            .paths
+            .disc_dir(album_id, disc_id.unwrap_or(NonZeroU8::MIN));
+        let album_dir = self.paths.album_dir(album_id);
+        // seafile hands out download links for files that don't exist, so look at the listings
+        let disc_files = self.dir_files(album_id, &disc_dir).await?;
+        let album_files = if album_dir != disc_dir {
+            self.dir_files(album_id, &album_dir).await?
+        } else {
+            Default::default()
+        };
+        // each name is looked for next to the tracks first, then in the album directory
+        for name in &self.cover_names {
+            if disc_files.contains(name) {
+                return Ok(format!("{disc_dir}/{name}"));
+            }
+            if album_files.contains(name) {
+                return Ok(format!("{album_dir}/{name}"));
+            }
+        }
+        Err(ProviderError::FileNotFound)
+    }
+
+    /// Resolves a download link, reusing a cached one if it has not expired yet.
+    pub async fn get_download_link(
+        &self,
+        album_id: &str,
+        path: impl Display,
+    ) -> anni_provider::Result<String> {
+        let path = path.to_string();
+        self.links
+            .get_or_fetch(path.clone(), || self.fetch_download_link(album_id, path))
             .await
     }
+
+    async fn fetch_download_link(
+        &self,
+        album_id: &str,
+        path: String,
+    ) -> anni_provider::Result<String> {
+        let url = format!(
+            "{server}/api2/repos/{repo_id}/file/?p={encoded}&reuse=1",
+            server = self.base,
+            repo_id = self.repo_of(album_id).await?,
+            encoded = utf8_percent_encode(&path, NON_ALPHANUMERIC),
+        );
+
+        let start = Instant::now();
+        let resp = self.api_get(&url).await?;
+        let status = resp.status();
+        let html = is_html(&resp);
+        // read as text first, so that error bodies can be logged as they are
+        let body = resp.text().await?;
+        metrics::histogram!("upstream_request_duration_seconds", "operation" => "download_link")
+            .record(start.elapsed().as_secs_f64());
+        self.warn_if_slow("download_link", &path, start);
+
+        // seafile answers in json, an html page comes from whatever sits in front of it
+        if html {
+            tracing::error!(
+                %path,
+                %status,
+                body = body_excerpt(&body),
+                "seafile api returned an html page instead of json, check the reverse proxy \
+                 in front of it and the configured base url"
+            );
+            return Err(ProviderError::GeneralError);
+        }
+        if !status.is_success() {
+            tracing::warn!(
+                %path,
+                %status,
+                body = body_excerpt(&body),
+                "seafile refused download link"
+            );
+            return Err(match status {
+                StatusCode::NOT_FOUND => ProviderError::FileNotFound,
+                _ => ProviderError::GeneralError,
+            });
+        }
+        match serde_json::from_str(&body) {
+            Ok(DownloadLink::Link(link)) => Ok(link),
+            Ok(DownloadLink::Error { error_msg }) => {
+                tracing::warn!(%path, %status, %error_msg, "seafile refused download link");
+                Err(ProviderError::GeneralError)
+            }
+            Err(e) => {
+                tracing::warn!(
+                    %path,
+                    error = %e,
+                    body = body_excerpt(&body),
+                    "unexpected download link response"
+                );
+                Err(ProviderError::GeneralError)
+            }
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -170,147 +878,4158 @@ impl AnniProvider for SeafileProvider {
         track_id: NonZeroU8,
         range: Range,
     ) -> anni_provider::Result<AudioResourceReader> {
-        let req = self.client.get(
-            self.get_download_link(format!("{album_id}/{disc_id}/{track_id}.flac"))
-                .await?,
+        let streamed = self
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(streamed.audio)
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        let path = self.find_cover(album_id, disc_id).await?;
+        let link = self.get_download_link(album_id, &path).await?;
+        let resp = self.retry.send(self.client.get(link)).await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(ProviderError::FileNotFound);
+        }
+        Ok(read_body(resp.error_for_status()?))
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        // files may have been moved or replaced, so their links and listings are fetched again
+        self.links.clear();
+        self.listings.clear();
+        if let Some(album_repos) = self.prepared.get_mut().unwrap().take() {
+            *self.album_repos.get_mut().unwrap() = album_repos;
+        }
+        Ok(())
+    }
+}
+
+impl AnniURLProvider for SeafileProvider {
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        let album_repos = self.fetch_album_repos().await?;
+        let albums = album_repos.keys().cloned().collect();
+        *self.prepared.lock().unwrap() = Some(album_repos);
+        Ok(albums)
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        let (path, extension) = self.find_track(album_id, disc_id, track_id).await?;
+        let link = self.get_download_link(album_id, &path).await?;
+        let start = Instant::now();
+        let audio = match self.parallel {
+            Some(parallel) => {
+                fetch_audio_parallel(
+                    &self.client,
+                    link,
+                    &extension,
+                    range,
+                    self.retry,
+                    self.probe_duration,
+                    parallel,
+                )
+                .await
+            }
+            None => {
+                fetch_audio(
+                    self.client.get(link),
+                    &extension,
+                    range,
+                    &self.retry,
+                    self.probe_duration,
+                )
+                .await
+            }
+        };
+        // until the response starts, the body is streamed afterwards
+        self.warn_if_slow("audio", &path, start);
+        audio
+    }
+
+    /// Resolves a download link for the track.
+    ///
+    /// Seafile's fileserver serves `Range` requests on download links, and clients resend their
+    /// `Range` header when following the redirect, so `range` is not encoded into the link.
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        let (path, _) = self.find_track(album_id, disc_id, track_id).await?;
+        let link = self.get_download_link(album_id, &path).await?;
+        if let Some(range) = range.to_range_header() {
+            tracing::debug!(%path, %range, "leaving range to the client");
+        }
+        Ok(Ok(link))
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        let path = self.find_cover(album_id, disc_id).await?;
+        Ok(Ok(self.get_download_link(album_id, path).await?))
+    }
+
+    /// Fetches a thumbnail rendered by seafile, which needs the api token and so can't be
+    /// linked to.
+    async fn get_cover_thumbnail_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+        size: u32,
+    ) -> anni_provider::Result<Option<Result<String, ResourceReader>>> {
+        let path = self.find_cover(album_id, disc_id).await?;
+        let url = format!(
+            "{server}/api2/repos/{repo_id}/thumbnail/?p={path}&size={size}",
+            server = self.base,
+            repo_id = self.repo_of(album_id).await?,
+            path = utf8_percent_encode(&path, NON_ALPHANUMERIC),
         );
-        let req = match range.to_range_header() {
-            Some(h) => req.header(RANGE, h),
-            None => req,
+        let resp = self.api_get(&url).await?;
+        if resp.status() == StatusCode::NOT_FOUND && !is_html(&resp) {
+            return Err(ProviderError::FileNotFound);
+        }
+        Ok(Some(Err(read_body(resp.error_for_status()?))))
+    }
+
+    /// Fetches only the header of the track, instead of streaming it through `get_audio`.
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        let (path, extension) = self.find_track(album_id, disc_id, track_id).await?;
+        let link = self.get_download_link(album_id, path).await?;
+        fetch_details(&self.client, &link, &extension, &self.retry).await
+    }
+
+    /// Finds the track and resolves its link once, and reads the details through the link.
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        let (path, extension) = self.find_track(album_id, disc_id, track_id).await?;
+        let link = self.get_download_link(album_id, &path).await?;
+        if let Some(range) = range.to_range_header() {
+            tracing::debug!(%path, %range, "leaving range to the client");
+        }
+        let details = fetch_details(&self.client, &link, &extension, &self.retry).await?;
+        Ok(Ok((link, details)))
+    }
+
+    /// Looks for the track with each configured extension in the listing of its directory.
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        match self.locate_track(album_id, disc_id, track_id).await {
+            Ok(found) => Ok(found.is_some()),
+            // the album is unknown
+            Err(ProviderError::FileNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        probe_discs(&*self.paths, album_id, |dir| async move {
+            let files = self.list_files(album_id, dir).await?;
+            // the layout doesn't include extensions
+            Ok(files
+                .iter()
+                .filter_map(|name| strip_extension(name, &self.extensions))
+                .map(String::from)
+                .collect())
+        })
+        .await
+        .map(Some)
+    }
+}
+
+pub struct LocalFileProvider {
+    root: PathBuf,
+    paths: Arc<dyn PathMapper>,
+    /// cover file names to look for, in order of preference
+    cover_names: Vec<String>,
+    /// audio file extensions to look for, in order of preference
+    extensions: Vec<String>,
+    /// read the duration of FLAC audio from its header while streaming it
+    probe_duration: bool,
+}
+
+impl LocalFileProvider {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            paths: Arc::new(DefaultPathMapper),
+            cover_names: vec![String::from("cover.jpg")],
+            extensions: vec![String::from("flac")],
+            probe_duration: true,
+        }
+    }
+
+    /// Reports a duration of 0 instead of reading it from the header of FLAC audio.
+    pub fn with_probe_duration(mut self, probe_duration: bool) -> Self {
+        self.probe_duration = probe_duration;
+        self
+    }
+
+    pub fn with_cover_names(mut self, cover_names: Vec<String>) -> Self {
+        self.cover_names = cover_names;
+        self
+    }
+
+    pub fn with_paths(mut self, paths: Arc<dyn PathMapper>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    fn disc_path(&self, album_id: &str, disc_id: NonZeroU8) -> PathBuf {
+        self.root.join(self.paths.disc_dir(album_id, disc_id))
+    }
+
+    fn audio_path(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        extension: &str,
+    ) -> PathBuf {
+        self.root.join(
+            self.paths
+                .audio_path(album_id, disc_id, track_id, extension),
+        )
+    }
+
+    /// Opens the file of a track with the first extension it exists with.
+    async fn open_track(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<(File, &str)> {
+        find_extension(&self.extensions, |extension| async move {
+            let path = self.audio_path(album_id, disc_id, track_id, extension);
+            File::open(path).await.map_err(handle_io_error)
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl AnniProvider for LocalFileProvider {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        let mut dir = tokio::fs::read_dir(&self.root).await?;
+        let mut albums = HashSet::new();
+        while let Some(entry) = dir.next_entry().await? {
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            if let Some(album_id) = entry.file_name().to_str() {
+                albums.insert(Cow::Owned(album_id.to_owned()));
+            }
+        }
+        Ok(albums)
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        let streamed = self
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(streamed.audio)
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        Ok(self.open_cover(album_id, disc_id).await?.0)
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        Ok(())
+    }
+}
+
+impl LocalFileProvider {
+    /// Opens the first cover that exists, along with the time it was last modified.
+    async fn open_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<(ResourceReader, Validators)> {
+        let dir = self.disc_path(album_id, disc_id.unwrap_or(NonZeroU8::MIN));
+        for name in &self.cover_names {
+            match File::open(dir.join(name)).await {
+                Ok(file) => {
+                    let validators = Validators {
+                        etag: None,
+                        last_modified: file.metadata().await?.modified().ok(),
+                    };
+                    return Ok((Box::pin(file), validators));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(ProviderError::FileNotFound)
+    }
+}
+
+// there is no url to redirect to, so files are always streamed by the server
+impl AnniURLProvider for LocalFileProvider {
+    fn lists_tracks_cheaply(&self) -> bool {
+        true
+    }
+
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        self.open_cover(album_id, disc_id).await.map(Err)
+    }
+
+    /// Reads the stream info of FLAC files before seeking, so it is known whatever the range.
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        let (mut file, extension) = self.open_track(album_id, disc_id, track_id).await?;
+        let size = file.metadata().await?.len();
+        if range.start > 0 && range.start >= size {
+            return Err(std::io::Error::other(RangeNotSatisfiable { size }).into());
+        }
+
+        // the requested range may not cover the header, so read it before seeking
+        let info = if self.probe_duration && extension == "flac" {
+            Some(read_stream_info(&mut file).await?.0)
+        } else {
+            None
         };
-        let resp = req.send().await?;
-        let content_length = resp.content_length().unwrap();
-        let (duration, reader) = read_response(resp).await?;
-        Ok(AudioResourceReader {
-            info: AudioInfo {
-                extension: String::from("flac"),
-                size: content_length as usize,
-                duration,
+
+        let end = range
+            .end
+            .map_or(size, |end| end.saturating_add(1).min(size));
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let reader = file.take(end.saturating_sub(range.start));
+
+        Ok(StreamedAudio {
+            audio: AudioResourceReader {
+                info: AudioInfo {
+                    extension: extension.to_owned(),
+                    size: size as usize,
+                    duration: info.as_ref().map_or(0, duration_secs),
+                },
+                range: Range {
+                    start: range.start,
+                    end: Some(end.saturating_sub(1)),
+                    total: Some(size),
+                },
+                reader: Box::pin(reader),
             },
-            range,
-            reader,
+            stream: info.as_ref().map(StreamInfo::from),
+        })
+    }
+
+    /// Streams the track along with its stream info, as there is no link to it.
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        let streamed = self
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(Err(streamed))
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        let found = find_extension(&self.extensions, |extension| async move {
+            let path = self.audio_path(album_id, disc_id, track_id, extension);
+            match tokio::fs::metadata(path).await.map_err(handle_io_error)? {
+                metadata if metadata.is_file() => Ok(()),
+                _ => Err(ProviderError::FileNotFound),
+            }
+        })
+        .await;
+        match found {
+            Ok(_) => Ok(true),
+            Err(ProviderError::FileNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        // a missing album has no disc directories either
+        tokio::fs::metadata(self.root.join(self.paths.album_dir(album_id)))
+            .await
+            .map_err(handle_io_error)?;
+        probe_discs(&*self.paths, album_id, |dir| async move {
+            let mut files = tokio::fs::read_dir(self.root.join(dir))
+                .await
+                .map_err(handle_io_error)?;
+            let mut names = Vec::new();
+            while let Some(file) = files.next_entry().await? {
+                // the layout doesn't include extensions
+                let name = file.file_name();
+                names.extend(
+                    name.to_str()
+                        .and_then(|n| strip_extension(n, &self.extensions))
+                        .map(String::from),
+                );
+            }
+            Ok(names)
+        })
+        .await
+        .map(Some)
+    }
+}
+
+/// Maps album, disc and track ids to the paths of files in a provider.
+///
+/// The default methods follow the `{album}/{disc}/{track}.{extension}` layout, with covers at
+/// `{album}/{disc}/cover.jpg` and album covers taken from the first disc.
+pub trait PathMapper: Send + Sync {
+    /// Directory holding the tracks of a disc.
+    fn disc_dir(&self, album_id: &str, disc_id: NonZeroU8) -> String {
+        format!("{album_id}/{disc_id}")
+    }
+
+    /// Path of a track, without the extension.
+    fn track_path(&self, album_id: &str, disc_id: NonZeroU8, track_id: NonZeroU8) -> String {
+        format!("{}/{track_id}", self.disc_dir(album_id, disc_id))
+    }
+
+    /// Path of a track stored with `extension`.
+    fn audio_path(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        extension: &str,
+    ) -> String {
+        format!(
+            "{}.{extension}",
+            self.track_path(album_id, disc_id, track_id)
+        )
+    }
+
+    fn cover_path(&self, album_id: &str, disc_id: Option<NonZeroU8>) -> String {
+        let disc_dir = self.disc_dir(album_id, disc_id.unwrap_or(NonZeroU8::MIN));
+        format!("{disc_dir}/cover.jpg")
+    }
+
+    /// Directory of an album, where covers are looked for after the disc directory.
+    fn album_dir(&self, album_id: &str) -> String {
+        album_id.to_owned()
+    }
+
+    /// Reads the track id from the name of a file in a disc directory, without the extension.
+    fn track_of(&self, _album_id: &str, _disc_id: NonZeroU8, name: &str) -> Option<NonZeroU8> {
+        parse_id(name)
+    }
+
+    /// Whether discs are stored in directories of their own.
+    fn has_disc_dir(&self) -> bool {
+        true
+    }
+}
+
+/// Parses an id written in plain digits.
+fn parse_id(digits: &str) -> Option<NonZeroU8> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// The layout of [`PathMapper`]'s default methods.
+pub struct DefaultPathMapper;
+
+impl PathMapper for DefaultPathMapper {}
+
+/// Name of disc directories, such as `Disc {disc}` or `CD{disc}`.
+///
+/// Maps paths in the layout of [`DefaultPathMapper`], with disc directories named after it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DiscDirFormat {
+    prefix: String,
+    suffix: String,
+}
+
+impl DiscDirFormat {
+    pub fn dir(&self, disc_id: NonZeroU8) -> String {
+        format!("{}{disc_id}{}", self.prefix, self.suffix)
+    }
+}
+
+impl TryFrom<String> for DiscDirFormat {
+    type Error = String;
+
+    fn try_from(format: String) -> Result<Self, Self::Error> {
+        let (prefix, suffix) = format
+            .split_once("{disc}")
+            .ok_or_else(|| format!("`{{disc}}` must be in `{format}`"))?;
+        if suffix.contains("{disc}") {
+            return Err(format!("`{{disc}}` must appear once in `{format}`"));
+        }
+        if format.contains('/') {
+            return Err(format!("`{format}` must not contain `/`"));
+        }
+        Ok(Self {
+            prefix: prefix.to_owned(),
+            suffix: suffix.to_owned(),
         })
     }
+}
+
+impl PathMapper for DiscDirFormat {
+    fn disc_dir(&self, album_id: &str, disc_id: NonZeroU8) -> String {
+        format!("{album_id}/{}", self.dir(disc_id))
+    }
+}
+
+pub struct S3Provider {
+    client: reqwest::Client,
+    bucket: Bucket,
+    credentials: Credentials,
+    /// how long presigned urls stay valid
+    expiry: Duration,
+    retry: Retry,
+    paths: Arc<dyn PathMapper>,
+    /// audio file extensions to look for, in order of preference
+    extensions: Vec<String>,
+    /// read the duration of FLAC audio from its header while streaming it
+    probe_duration: bool,
+}
+
+impl S3Provider {
+    pub fn new(
+        client: reqwest::Client,
+        bucket: Bucket,
+        credentials: Credentials,
+        expiry: Duration,
+        retry: Retry,
+        paths: Arc<dyn PathMapper>,
+    ) -> Self {
+        Self {
+            client,
+            bucket,
+            credentials,
+            expiry,
+            retry,
+            paths,
+            extensions: vec![String::from("flac")],
+            probe_duration: true,
+        }
+    }
+
+    /// Reports a duration of 0 instead of reading it from the header of FLAC audio.
+    pub fn with_probe_duration(mut self, probe_duration: bool) -> Self {
+        self.probe_duration = probe_duration;
+        self
+    }
+
+    /// Looks for tracks with each of `extensions` in order, which takes a request for every
+    /// extension a track is missing with.
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Asks for the headers of an object, as presigning a link doesn't look at the bucket.
+    async fn head(&self, path: &str) -> anni_provider::Result<()> {
+        let url = self
+            .bucket
+            .head_object(Some(&self.credentials), path)
+            .sign(self.expiry);
+        let resp = self.retry.send(self.client.head(url)).await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(ProviderError::FileNotFound);
+        }
+        resp.error_for_status()?;
+        Ok(())
+    }
+
+    /// Finds the path of a track, looking at the bucket only if there are several extensions to
+    /// choose from.
+    async fn find_track(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<String> {
+        if let [extension] = self.extensions.as_slice() {
+            return Ok(self
+                .paths
+                .audio_path(album_id, disc_id, track_id, extension));
+        }
+        let (path, _) = find_extension(&self.extensions, |extension| {
+            let path = self
+                .paths
+                .audio_path(album_id, disc_id, track_id, extension);
+            async move { self.head(&path).await.map(|()| path) }
+        })
+        .await?;
+        Ok(path)
+    }
+
+    pub fn presign(&self, path: &str) -> String {
+        self.bucket
+            .get_object(Some(&self.credentials), path)
+            .sign(self.expiry)
+            .to_string()
+    }
+
+    /// Lists top level prefixes of the bucket, which are album ids.
+    pub async fn list_albums(&self) -> anni_provider::Result<Vec<String>> {
+        let mut albums = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let mut action = self.bucket.list_objects_v2(Some(&self.credentials));
+            action.query_mut().insert("delimiter", "/");
+            if let Some(token) = &continuation_token {
+                action.with_continuation_token(token);
+            }
+
+            let body = self
+                .client
+                .get(action.sign(self.expiry))
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            let list = ListObjectsV2::parse_response(&body)
+                .map_err(|_| ProviderError::GeneralError)?;
+
+            albums.extend(
+                list.common_prefixes
+                    .into_iter()
+                    .map(|prefix| prefix.prefix.trim_end_matches('/').to_owned()),
+            );
+            match list.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => break,
+            }
+        }
+        Ok(albums)
+    }
+}
+
+#[async_trait::async_trait]
+impl AnniProvider for S3Provider {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        Ok(self
+            .list_albums()
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        let streamed = self
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(streamed.audio)
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        let path = self.paths.cover_path(album_id, disc_id);
+        let resp = self.client.get(self.presign(&path)).send().await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(ProviderError::FileNotFound);
+        }
+        Ok(read_body(resp.error_for_status()?))
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        Ok(())
+    }
+}
+
+impl AnniURLProvider for S3Provider {
+    /// Lists the albums, so that a reload fails before the write lock is taken if the bucket
+    /// can't be listed.
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        Ok(self.list_albums().await?.into_iter().collect())
+    }
+
+    /// Asks for the headers of the track with each extension.
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        let found = find_extension(&self.extensions, |extension| {
+            let path = self
+                .paths
+                .audio_path(album_id, disc_id, track_id, extension);
+            async move { self.head(&path).await }
+        })
+        .await;
+        match found {
+            Ok(_) => Ok(true),
+            Err(ProviderError::FileNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        // a missing object is answered with 404, so the extensions are tried with the audio itself
+        let (streamed, _) = find_extension(&self.extensions, |extension| {
+            let path = self
+                .paths
+                .audio_path(album_id, disc_id, track_id, extension);
+            let req = self.client.get(self.presign(&path));
+            fetch_audio(req, extension, range, &self.retry, self.probe_duration)
+        })
+        .await?;
+        Ok(streamed)
+    }
+
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        _range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        let path = self.find_track(album_id, disc_id, track_id).await?;
+        Ok(Ok(self.presign(&path)))
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        Ok(Ok(self.presign(&self.paths.cover_path(album_id, disc_id))))
+    }
+}
+
+/// An OAuth2 access token, refreshed on demand using a refresh token.
+pub struct OAuthToken {
+    client: reqwest::Client,
+    token_url: String,
+    params: Vec<(&'static str, String)>,
+    state: tokio::sync::Mutex<TokenState>,
+}
+
+struct TokenState {
+    /// replaced whenever the server rotates it
+    refresh_token: String,
+    current: Option<(String, Instant)>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+    refresh_token: Option<String>,
+}
+
+impl OAuthToken {
+    pub fn google(
+        client: reqwest::Client,
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    ) -> Self {
+        Self::new(
+            client,
+            String::from("https://oauth2.googleapis.com/token"),
+            client_id,
+            client_secret,
+            refresh_token,
+        )
+    }
+
+    /// Creates a token for the Microsoft identity platform.
+    ///
+    /// `tenant` is a tenant id, or `common` for personal and work accounts alike.
+    pub fn microsoft(
+        client: reqwest::Client,
+        tenant: &str,
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    ) -> Self {
+        Self::new(
+            client,
+            format!("https://login.microsoftonline.com/{tenant}/oauth2/v2.0/token"),
+            client_id,
+            client_secret,
+            refresh_token,
+        )
+    }
+
+    fn new(
+        client: reqwest::Client,
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    ) -> Self {
+        Self {
+            client,
+            token_url,
+            params: vec![
+                ("grant_type", String::from("refresh_token")),
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+            ],
+            state: tokio::sync::Mutex::new(TokenState {
+                refresh_token,
+                current: None,
+            }),
+        }
+    }
+
+    /// Returns a valid access token, refreshing it if it has expired.
+    pub async fn get(&self) -> anni_provider::Result<String> {
+        let mut state = self.state.lock().await;
+        if let Some((token, expires_at)) = &state.current {
+            if Instant::now() < *expires_at {
+                return Ok(token.clone());
+            }
+        }
+
+        let mut params = self.params.clone();
+        params.push(("refresh_token", state.refresh_token.clone()));
+        let resp: TokenResponse = self
+            .client
+            .post(&self.token_url)
+            .form(&params)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // refresh a bit early so that the token doesn't expire in flight
+        let expires_at = Instant::now() + Duration::from_secs(resp.expires_in.saturating_sub(60));
+        state.current = Some((resp.access_token.clone(), expires_at));
+        if let Some(refresh_token) = resp.refresh_token {
+            state.refresh_token = refresh_token;
+        }
+        Ok(resp.access_token)
+    }
+}
+
+const DRIVE_FILES_API: &str = "https://www.googleapis.com/drive/v3/files";
+
+pub struct GDriveProvider {
+    client: reqwest::Client,
+    token: OAuthToken,
+    folder_id: String,
+    /// path -> file, reset to the album folders on reload
+    files: RwLock<HashMap<String, DriveFile>>,
+    /// album folders listed by `prepare_reload`, swapped in by the following `reload`
+    prepared: Mutex<Option<HashMap<String, DriveFile>>>,
+    retry: Retry,
+    paths: Arc<dyn PathMapper>,
+    /// audio file extensions to look for, in order of preference
+    extensions: Vec<String>,
+    /// read the duration of FLAC audio from its header while streaming it
+    probe_duration: bool,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DriveFile {
+    id: String,
+    name: String,
+    /// only present if the file is shared by link
+    web_content_link: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DriveFileList {
+    files: Vec<DriveFile>,
+    next_page_token: Option<String>,
+}
+
+impl GDriveProvider {
+    pub fn new(
+        client: reqwest::Client,
+        token: OAuthToken,
+        folder_id: String,
+        retry: Retry,
+        paths: Arc<dyn PathMapper>,
+    ) -> Self {
+        Self {
+            client,
+            token,
+            folder_id,
+            files: Default::default(),
+            prepared: Default::default(),
+            retry,
+            paths,
+            extensions: vec![String::from("flac")],
+            probe_duration: true,
+        }
+    }
+
+    /// Reports a duration of 0 instead of reading it from the header of FLAC audio.
+    pub fn with_probe_duration(mut self, probe_duration: bool) -> Self {
+        self.probe_duration = probe_duration;
+        self
+    }
+
+    /// Looks for tracks with each of `extensions` in order, which takes a request for every
+    /// extension a track is missing with until it's found.
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    async fn list(&self, query: &str) -> anni_provider::Result<Vec<DriveFile>> {
+        let mut files = Vec::new();
+        let mut page_token = None;
+        loop {
+            let req = self
+                .client
+                .get(DRIVE_FILES_API)
+                .bearer_auth(self.token.get().await?)
+                .query(&[
+                    ("q", query),
+                    ("fields", "nextPageToken, files(id, name, webContentLink)"),
+                    ("pageSize", "1000"),
+                ]);
+            let req = match &page_token {
+                Some(token) => req.query(&[("pageToken", token)]),
+                None => req,
+            };
+
+            let list: DriveFileList = self
+                .retry
+                .send(req)
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            files.extend(list.files);
+            match list.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+        Ok(files)
+    }
+
+    pub async fn list_albums(&self) -> anni_provider::Result<Vec<String>> {
+        Ok(self
+            .list_album_folders()
+            .await?
+            .into_iter()
+            .map(|folder| folder.name)
+            .collect())
+    }
+
+    async fn list_album_folders(&self) -> anni_provider::Result<Vec<DriveFile>> {
+        let query = format!(
+            "'{}' in parents and mimeType = 'application/vnd.google-apps.folder' and trashed = false",
+            self.folder_id
+        );
+        self.list(&query).await
+    }
+
+    /// Finds a file by its path relative to the root folder.
+    async fn find(&self, path: &str) -> anni_provider::Result<DriveFile> {
+        let cached = self.files.read().unwrap().get(path).cloned();
+        if let Some(file) = cached {
+            return Ok(file);
+        }
+
+        // start from the album folder if it's known, which saves a request
+        let (mut parent, names) = match path.split_once('/') {
+            Some((album_id, rest)) => match self.files.read().unwrap().get(album_id) {
+                Some(folder) => (folder.id.clone(), rest),
+                None => (self.folder_id.clone(), path),
+            },
+            None => (self.folder_id.clone(), path),
+        };
+        let mut file = None;
+        for name in names.split('/') {
+            let query = format!(
+                "'{parent}' in parents and name = '{}' and trashed = false",
+                name.replace('\\', "\\\\").replace('\'', "\\'")
+            );
+            let found = self
+                .list(&query)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or(ProviderError::FileNotFound)?;
+            parent = found.id.clone();
+            file = Some(found);
+        }
+
+        let file = file.ok_or(ProviderError::InvalidPath)?;
+        self.files
+            .write()
+            .unwrap()
+            .insert(path.to_owned(), file.clone());
+        Ok(file)
+    }
+
+    /// Finds the file of a track with the first extension it exists with.
+    async fn find_track(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<(DriveFile, &str)> {
+        find_extension(&self.extensions, |extension| {
+            let path = self
+                .paths
+                .audio_path(album_id, disc_id, track_id, extension);
+            async move { self.find(&path).await }
+        })
+        .await
+    }
+
+    async fn download(&self, file: &DriveFile) -> anni_provider::Result<reqwest::RequestBuilder> {
+        Ok(self
+            .client
+            .get(format!("{DRIVE_FILES_API}/{}?alt=media", file.id))
+            .bearer_auth(self.token.get().await?))
+    }
+}
+
+#[async_trait::async_trait]
+impl AnniProvider for GDriveProvider {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        Ok(self
+            .list_albums()
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        let streamed = self
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(streamed.audio)
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        let file = self.find(&self.paths.cover_path(album_id, disc_id)).await?;
+        let resp = self.retry.send(self.download(&file).await?).await?;
+        Ok(read_body(resp.error_for_status()?))
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        *self.files.get_mut().unwrap() =
+            self.prepared.get_mut().unwrap().take().unwrap_or_default();
+        Ok(())
+    }
+}
+
+impl AnniURLProvider for GDriveProvider {
+    /// Looks the track up by its path, which is cached once found.
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        match self.find_track(album_id, disc_id, track_id).await {
+            Ok(_) => Ok(true),
+            Err(ProviderError::FileNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        let folders = self.list_album_folders().await?;
+        let albums = folders.iter().map(|folder| folder.name.clone()).collect();
+        *self.prepared.lock().unwrap() = Some(
+            folders
+                .into_iter()
+                .map(|folder| (folder.name.clone(), folder))
+                .collect(),
+        );
+        Ok(albums)
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        let (file, extension) = self.find_track(album_id, disc_id, track_id).await?;
+        fetch_audio(
+            self.download(&file).await?,
+            extension,
+            range,
+            &self.retry,
+            self.probe_duration,
+        )
+        .await
+    }
+
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        let (file, extension) = self.find_track(album_id, disc_id, track_id).await?;
+        match file.web_content_link {
+            Some(link) => Ok(Ok(link)),
+            // the file is not shared, so it can only be served through us
+            None => {
+                let req = self.download(&file).await?;
+                fetch_audio(req, extension, range, &self.retry, self.probe_duration)
+                    .await
+                    .map(|streamed| Err(streamed.audio))
+            }
+        }
+    }
+
+    /// Streams files that aren't shared along with the stream info read on the way.
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        let (file, extension) = self.find_track(album_id, disc_id, track_id).await?;
+        match file.web_content_link {
+            Some(link) => {
+                let details = self.get_audio_details(album_id, disc_id, track_id).await?;
+                Ok(Ok((link, details)))
+            }
+            None => {
+                let req = self.download(&file).await?;
+                fetch_audio(req, extension, range, &self.retry, self.probe_duration)
+                    .await
+                    .map(Result::Err)
+            }
+        }
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        let file = self.find(&self.paths.cover_path(album_id, disc_id)).await?;
+        match file.web_content_link {
+            Some(link) => Ok(Ok(link)),
+            None => self.get_cover(album_id, disc_id).await.map(Result::Err),
+        }
+    }
+}
+
+const GRAPH_DRIVE_API: &str = "https://graph.microsoft.com/v1.0/me/drive";
+
+pub struct OneDriveProvider {
+    client: reqwest::Client,
+    token: OAuthToken,
+    /// segments of the folder holding albums, relative to the drive root
+    folder: Vec<String>,
+    retry: Retry,
+    paths: Arc<dyn PathMapper>,
+    /// audio file extensions to look for, in order of preference
+    extensions: Vec<String>,
+    /// read the duration of FLAC audio from its header while streaming it
+    probe_duration: bool,
+}
+
+#[derive(Deserialize)]
+struct DriveItem {
+    name: String,
+    /// pre-authenticated and short-lived, only present on files
+    #[serde(rename = "@microsoft.graph.downloadUrl")]
+    download_url: Option<String>,
+    /// only present on folders
+    folder: Option<serde::de::IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+struct DriveItemList {
+    value: Vec<DriveItem>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+impl OneDriveProvider {
+    pub fn new(
+        client: reqwest::Client,
+        token: OAuthToken,
+        folder: &str,
+        retry: Retry,
+        paths: Arc<dyn PathMapper>,
+    ) -> Self {
+        Self {
+            client,
+            token,
+            folder: folder
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(String::from)
+                .collect(),
+            retry,
+            paths,
+            extensions: vec![String::from("flac")],
+            probe_duration: true,
+        }
+    }
+
+    /// Reports a duration of 0 instead of reading it from the header of FLAC audio.
+    pub fn with_probe_duration(mut self, probe_duration: bool) -> Self {
+        self.probe_duration = probe_duration;
+        self
+    }
+
+    /// Looks for tracks with each of `extensions` in order, which takes a request for every
+    /// extension a track is missing with.
+    pub fn with_extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Builds the url of an item by its path relative to the album folder.
+    ///
+    /// An empty path addresses the album folder itself, and `suffix` is appended as is, such as
+    /// `children` to list a folder.
+    fn item_url(&self, path: &str, suffix: Option<&str>) -> reqwest::Url {
+        let mut segments: Vec<_> = self
+            .folder
+            .iter()
+            .map(String::as_str)
+            .chain(path.split('/').filter(|segment| !segment.is_empty()))
+            .collect();
+
+        let mut url = reqwest::Url::parse(GRAPH_DRIVE_API).unwrap();
+        {
+            let mut url_segments = url.path_segments_mut().unwrap();
+            match segments.pop() {
+                // the drive root has no path syntax
+                None => {
+                    url_segments.push("root");
+                }
+                // root:/path/to/item or root:/path/to/item:/suffix
+                Some(last) => {
+                    url_segments.push("root:").extend(segments);
+                    match suffix {
+                        Some(_) => url_segments.push(&format!("{last}:")),
+                        None => url_segments.push(last),
+                    };
+                }
+            }
+            if let Some(suffix) = suffix {
+                url_segments.push(suffix);
+            }
+        }
+        url
+    }
+
+    pub async fn list_albums(&self) -> anni_provider::Result<Vec<String>> {
+        let mut albums = Vec::new();
+        let mut url = self.item_url("", Some("children"));
+        url.query_pairs_mut()
+            .append_pair("$select", "name,folder")
+            .append_pair("$top", "1000");
+        let mut next = Some(url.to_string());
+        while let Some(url) = next {
+            let req = self.client.get(url).bearer_auth(self.token.get().await?);
+            let list: DriveItemList = self
+                .retry
+                .send(req)
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            albums.extend(
+                list.value
+                    .into_iter()
+                    .filter(|item| item.folder.is_some())
+                    .map(|item| item.name),
+            );
+            next = list.next_link;
+        }
+        Ok(albums)
+    }
+
+    /// Resolves a temporary download url of a file.
+    async fn download_url(&self, path: &str) -> anni_provider::Result<String> {
+        let req = self
+            .client
+            .get(self.item_url(path, None))
+            .bearer_auth(self.token.get().await?);
+        let resp = self.retry.send(req).await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(ProviderError::FileNotFound);
+        }
+        let item: DriveItem = resp.error_for_status()?.json().await?;
+        item.download_url.ok_or(ProviderError::FileNotFound)
+    }
+
+    /// Resolves a download url of a track with the first extension it exists with.
+    async fn track_url(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<(String, &str)> {
+        find_extension(&self.extensions, |extension| {
+            let path = self
+                .paths
+                .audio_path(album_id, disc_id, track_id, extension);
+            async move { self.download_url(&path).await }
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl AnniProvider for OneDriveProvider {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        Ok(self
+            .list_albums()
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        let streamed = self
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(streamed.audio)
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        let url = self
+            .download_url(&self.paths.cover_path(album_id, disc_id))
+            .await?;
+        let resp = self.retry.send(self.client.get(url)).await?;
+        // the item may be deleted between resolving and fetching it
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(ProviderError::FileNotFound);
+        }
+        Ok(read_body(resp.error_for_status()?))
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        Ok(())
+    }
+}
+
+impl AnniURLProvider for OneDriveProvider {
+    /// Lists the albums, so that a reload fails before the write lock is taken if the folder
+    /// can't be listed.
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        Ok(self.list_albums().await?.into_iter().collect())
+    }
+
+    /// Looks the track up by its path, with a single metadata request.
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        match self.track_url(album_id, disc_id, track_id).await {
+            Ok(_) => Ok(true),
+            Err(ProviderError::FileNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        let (url, extension) = self.track_url(album_id, disc_id, track_id).await?;
+        fetch_audio(
+            self.client.get(url),
+            extension,
+            range,
+            &self.retry,
+            self.probe_duration,
+        )
+        .await
+    }
+
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        _range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        let (url, _) = self.track_url(album_id, disc_id, track_id).await?;
+        Ok(Ok(url))
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        self.download_url(&self.paths.cover_path(album_id, disc_id))
+            .await
+            .map(Result::Ok)
+    }
+}
+
+/// One of the supported providers, chosen at runtime.
+///
+/// Delegates every method to the wrapped provider, so that the server is only instantiated
+/// for a single provider type.
+pub enum AnyProvider {
+    Seafile(SeafileProvider),
+    Webdav(WebdavProvider),
+    Local(LocalFileProvider),
+    S3(S3Provider),
+    GDrive(GDriveProvider),
+    OneDrive(OneDriveProvider),
+}
+
+macro_rules! dispatch {
+    ($self:expr, $provider:ident => $body:expr) => {
+        match $self {
+            AnyProvider::Seafile($provider) => $body,
+            AnyProvider::Webdav($provider) => $body,
+            AnyProvider::Local($provider) => $body,
+            AnyProvider::S3($provider) => $body,
+            AnyProvider::GDrive($provider) => $body,
+            AnyProvider::OneDrive($provider) => $body,
+        }
+    };
+}
+
+#[async_trait::async_trait]
+impl AnniProvider for AnyProvider {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        dispatch!(self, provider => provider.albums().await)
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        dispatch!(self, provider => provider.get_audio(album_id, disc_id, track_id, range).await)
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        dispatch!(self, provider => provider.get_cover(album_id, disc_id).await)
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        dispatch!(self, provider => provider.reload().await)
+    }
+}
+
+impl AnniURLProvider for AnyProvider {
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        dispatch!(self, provider => {
+            provider.get_audio_link(album_id, disc_id, track_id, range).await
+        })
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        dispatch!(self, provider => {
+            provider
+                .get_audio_stream(album_id, disc_id, track_id, range)
+                .await
+        })
+    }
+
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        dispatch!(self, provider => {
+            provider
+                .get_audio_link_details(album_id, disc_id, track_id, range)
+                .await
+        })
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        dispatch!(self, provider => provider.get_cover_link(album_id, disc_id).await)
+    }
+
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        dispatch!(self, provider => provider.get_cover_validated(album_id, disc_id).await)
+    }
+
+    async fn get_cover_thumbnail_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+        size: u32,
+    ) -> anni_provider::Result<Option<Result<String, ResourceReader>>> {
+        dispatch!(self, provider => {
+            provider.get_cover_thumbnail_link(album_id, disc_id, size).await
+        })
+    }
+
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        dispatch!(self, provider => {
+            provider.get_audio_details(album_id, disc_id, track_id).await
+        })
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        dispatch!(self, provider => provider.exists(album_id, disc_id, track_id).await)
+    }
+
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        dispatch!(self, provider => provider.prepare_reload().await)
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        dispatch!(self, provider => provider.list_tracks(album_id).await)
+    }
+
+    fn lists_tracks_cheaply(&self) -> bool {
+        dispatch!(self, provider => provider.lists_tracks_cheaply())
+    }
+}
+
+type CoverKey = (String, Option<NonZeroU8>);
+
+/// Keeps recently requested covers in memory, up to a total size in bytes.
+///
+/// Covers the wrapped provider serves as readers are cached as images, and cover links are
+/// cached for `link_ttl`, as they may expire upstream. With a capacity of 0, nothing is cached
+/// and covers pass straight through.
+pub struct CoverCache<P> {
+    inner: P,
+    capacity: usize,
+    link_ttl: Duration,
+    entries: Mutex<CoverEntries>,
+}
+
+struct CoverEntries {
+    covers: LruCache<CoverKey, CachedCover>,
+    /// total size of all cached covers
+    size: usize,
+}
+
+#[derive(Clone)]
+enum CachedCover {
+    Image(Arc<[u8]>, Validators),
+    Link(String, Instant),
+}
+
+impl CachedCover {
+    fn size(&self) -> usize {
+        match self {
+            Self::Image(image, _) => image.len(),
+            Self::Link(link, _) => link.len(),
+        }
+    }
+}
+
+impl<P> CoverCache<P> {
+    pub fn new(inner: P, capacity: usize, link_ttl: Duration) -> Self {
+        Self {
+            inner,
+            capacity,
+            link_ttl,
+            entries: Mutex::new(CoverEntries {
+                covers: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    fn get(&self, key: &CoverKey) -> Option<CachedCover> {
+        let mut entries = self.entries.lock().unwrap();
+        let cover = entries.covers.get(key)?.clone();
+        if let CachedCover::Link(_, fetched_at) = &cover {
+            if fetched_at.elapsed() >= self.link_ttl {
+                if let Some(expired) = entries.covers.pop(key) {
+                    entries.size -= expired.size();
+                }
+                return None;
+            }
+        }
+        Some(cover)
+    }
+
+    fn insert(&self, key: CoverKey, cover: CachedCover) {
+        let size = cover.size();
+        if size > self.capacity {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(replaced) = entries.covers.put(key, cover) {
+            entries.size -= replaced.size();
+        }
+        entries.size += size;
+        while entries.size > self.capacity {
+            match entries.covers.pop_lru() {
+                Some((_, evicted)) => entries.size -= evicted.size(),
+                None => break,
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: AnniProvider + Send + Sync> AnniProvider for CoverCache<P> {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        self.inner.albums().await
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        self.inner
+            .get_audio(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        self.inner.get_cover(album_id, disc_id).await
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        let entries = self.entries.get_mut().unwrap();
+        entries.covers.clear();
+        entries.size = 0;
+        self.inner.reload().await
+    }
+}
+
+impl<P: AnniURLProvider + Send + Sync> AnniURLProvider for CoverCache<P> {
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        self.inner
+            .get_audio_link(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        self.inner
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        self.inner
+            .get_audio_link_details(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        let cover = self.get_cover_validated(album_id, disc_id).await?;
+        Ok(cover.map_err(|(reader, _)| reader))
+    }
+
+    /// Serves cached covers with the validators they were fetched with.
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        if self.capacity == 0 {
+            return self.inner.get_cover_validated(album_id, disc_id).await;
+        }
+        let key = (album_id.to_owned(), disc_id);
+        match self.get(&key) {
+            Some(CachedCover::Image(image, validators)) => {
+                return Ok(Err((Box::pin(Cursor::new(image)), validators)))
+            }
+            Some(CachedCover::Link(link, _)) => return Ok(Ok(link)),
+            None => {}
+        }
+
+        match self.inner.get_cover_validated(album_id, disc_id).await? {
+            Ok(link) => {
+                self.insert(key, CachedCover::Link(link.clone(), Instant::now()));
+                Ok(Ok(link))
+            }
+            Err((mut reader, validators)) => {
+                // read one byte past the capacity to tell whether the cover fits
+                let mut image = Vec::new();
+                (&mut reader)
+                    .take(self.capacity as u64 + 1)
+                    .read_to_end(&mut image)
+                    .await?;
+                if image.len() > self.capacity {
+                    return Ok(Err((
+                        Box::pin(Cursor::new(image).chain(reader)),
+                        validators,
+                    )));
+                }
+
+                let image: Arc<[u8]> = image.into();
+                self.insert(key, CachedCover::Image(image.clone(), validators.clone()));
+                Ok(Err((Box::pin(Cursor::new(image)), validators)))
+            }
+        }
+    }
+
+    async fn get_cover_thumbnail_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+        size: u32,
+    ) -> anni_provider::Result<Option<Result<String, ResourceReader>>> {
+        self.inner
+            .get_cover_thumbnail_link(album_id, disc_id, size)
+            .await
+    }
+
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        self.inner
+            .get_audio_details(album_id, disc_id, track_id)
+            .await
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        self.inner.exists(album_id, disc_id, track_id).await
+    }
+
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        self.inner.prepare_reload().await
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        self.inner.list_tracks(album_id).await
+    }
+
+    fn lists_tracks_cheaply(&self) -> bool {
+        self.inner.lists_tracks_cheaply()
+    }
+
+    fn album_titles(&self, album_id: &str) -> Option<AlbumTitles> {
+        self.inner.album_titles(album_id)
+    }
+}
+
+/// Whether the albums listed in an album filter are the only ones served or the ones hidden.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterMode {
+    Allow,
+    Deny,
+}
+
+/// Serves only some albums of the wrapped provider, as if the others didn't exist.
+///
+/// Album ids are read from a file, one per line, and re-read on reload. Empty lines and lines
+/// starting with `#` are ignored.
+pub struct FilteredProvider<P> {
+    inner: P,
+    mode: FilterMode,
+    /// `None` if every album is served
+    path: Option<PathBuf>,
+    albums: HashSet<String>,
+}
+
+impl<P> FilteredProvider<P> {
+    pub fn new(inner: P, mode: FilterMode, path: PathBuf) -> std::io::Result<Self> {
+        let albums = parse_album_list(&std::fs::read_to_string(&path)?);
+        Ok(Self {
+            inner,
+            mode,
+            path: Some(path),
+            albums,
+        })
+    }
+
+    /// Serves every album of the wrapped provider.
+    pub fn unfiltered(inner: P) -> Self {
+        Self {
+            inner,
+            mode: FilterMode::Deny,
+            path: None,
+            albums: HashSet::new(),
+        }
+    }
+
+    fn is_visible(&self, album_id: &str) -> bool {
+        match self.mode {
+            FilterMode::Allow => self.albums.contains(album_id),
+            FilterMode::Deny => !self.albums.contains(album_id),
+        }
+    }
+
+    fn check(&self, album_id: &str) -> anni_provider::Result<()> {
+        if self.is_visible(album_id) {
+            Ok(())
+        } else {
+            Err(ProviderError::FileNotFound)
+        }
+    }
+}
+
+fn parse_album_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl<P: AnniProvider + Send + Sync> AnniProvider for FilteredProvider<P> {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        let mut albums = self.inner.albums().await?;
+        albums.retain(|album_id| self.is_visible(album_id));
+        Ok(albums)
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        self.check(album_id)?;
+        self.inner
+            .get_audio(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        self.check(album_id)?;
+        self.inner.get_cover(album_id, disc_id).await
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        if let Some(path) = &self.path {
+            // a list that can't be read keeps the previous one rather than exposing everything
+            let list = tokio::fs::read_to_string(path).await?;
+            self.albums = parse_album_list(&list);
+        }
+        self.inner.reload().await
+    }
+}
+
+impl<P: AnniURLProvider + Send + Sync> AnniURLProvider for FilteredProvider<P> {
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        self.check(album_id)?;
+        self.inner
+            .get_audio_link(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        self.check(album_id)?;
+        self.inner
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        self.check(album_id)?;
+        self.inner
+            .get_audio_link_details(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        self.check(album_id)?;
+        self.inner.get_cover_link(album_id, disc_id).await
+    }
+
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        self.check(album_id)?;
+        self.inner.get_cover_validated(album_id, disc_id).await
+    }
+
+    async fn get_cover_thumbnail_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+        size: u32,
+    ) -> anni_provider::Result<Option<Result<String, ResourceReader>>> {
+        self.check(album_id)?;
+        self.inner
+            .get_cover_thumbnail_link(album_id, disc_id, size)
+            .await
+    }
+
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        self.check(album_id)?;
+        self.inner
+            .get_audio_details(album_id, disc_id, track_id)
+            .await
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        if !self.is_visible(album_id) {
+            return Ok(false);
+        }
+        self.inner.exists(album_id, disc_id, track_id).await
+    }
+
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        let mut albums = self.inner.prepare_reload().await?;
+        albums.retain(|album_id| self.is_visible(album_id));
+        Ok(albums)
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        self.check(album_id)?;
+        self.inner.list_tracks(album_id).await
+    }
+
+    fn lists_tracks_cheaply(&self) -> bool {
+        self.inner.lists_tracks_cheaply()
+    }
+
+    fn album_titles(&self, album_id: &str) -> Option<AlbumTitles> {
+        if !self.is_visible(album_id) {
+            return None;
+        }
+        self.inner.album_titles(album_id)
+    }
+}
+
+/// Bounds how many requests are sent to an upstream at once.
+///
+/// Requests beyond the limit queue for up to `wait`, then fail with [`UpstreamBusy`].
+pub struct UpstreamLimit {
+    permits: Arc<Semaphore>,
+    wait: Duration,
+}
+
+impl UpstreamLimit {
+    pub fn new(max_requests: usize, wait: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_requests)),
+            wait,
+        }
+    }
+
+    /// A limit no number of requests reaches, for upstreams without one.
+    pub fn unlimited() -> Self {
+        Self::new(Semaphore::MAX_PERMITS, Duration::MAX)
+    }
+
+    async fn acquire(&self) -> anni_provider::Result<OwnedSemaphorePermit> {
+        match tokio::time::timeout(self.wait, self.permits.clone().acquire_owned()).await {
+            Ok(permit) => Ok(permit.expect("the semaphore is never closed")),
+            Err(_) => {
+                metrics::counter!("upstream_limit_rejections_total").increment(1);
+                Err(std::io::Error::other(UpstreamBusy).into())
+            }
+        }
+    }
+}
+
+/// A request waited longer than allowed for the upstream concurrency limit.
+#[derive(Debug)]
+pub struct UpstreamBusy;
+
+impl Display for UpstreamBusy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("too many concurrent upstream requests")
+    }
+}
+
+impl std::error::Error for UpstreamBusy {}
+
+/// Keeps a permit until the wrapped reader is dropped, so that streamed bodies count against
+/// the limit until they are fully read.
+struct PermitReader {
+    reader: ResourceReader,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl AsyncRead for PermitReader {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        self.reader.as_mut().poll_read(cx, buf)
+    }
+}
+
+fn hold_permit(reader: ResourceReader, permit: OwnedSemaphorePermit) -> ResourceReader {
+    Box::pin(PermitReader {
+        reader,
+        _permit: permit,
+    })
+}
+
+/// Limits concurrent requests to the upstream of another provider.
+///
+/// Every call to the inner provider takes a permit of the [`UpstreamLimit`], and readers it
+/// returns keep theirs until they are dropped.
+pub struct ConcurrencyLimit<P> {
+    inner: P,
+    limit: UpstreamLimit,
+}
+
+impl<P> ConcurrencyLimit<P> {
+    pub fn new(inner: P, limit: UpstreamLimit) -> Self {
+        Self { inner, limit }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: AnniProvider + Send + Sync> AnniProvider for ConcurrencyLimit<P> {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        let _permit = self.limit.acquire().await?;
+        self.inner.albums().await
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        let permit = self.limit.acquire().await?;
+        let mut audio = self
+            .inner
+            .get_audio(album_id, disc_id, track_id, range)
+            .await?;
+        audio.reader = hold_permit(audio.reader, permit);
+        Ok(audio)
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        let permit = self.limit.acquire().await?;
+        let reader = self.inner.get_cover(album_id, disc_id).await?;
+        Ok(hold_permit(reader, permit))
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        let _permit = self.limit.acquire().await?;
+        self.inner.reload().await
+    }
+}
+
+impl<P: AnniURLProvider + Send + Sync> AnniURLProvider for ConcurrencyLimit<P> {
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        let permit = self.limit.acquire().await?;
+        let link = self
+            .inner
+            .get_audio_link(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(link.map_err(|mut audio| {
+            audio.reader = hold_permit(audio.reader, permit);
+            audio
+        }))
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        let permit = self.limit.acquire().await?;
+        let mut streamed = self
+            .inner
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await?;
+        streamed.audio.reader = hold_permit(streamed.audio.reader, permit);
+        Ok(streamed)
+    }
+
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        let permit = self.limit.acquire().await?;
+        let link = self
+            .inner
+            .get_audio_link_details(album_id, disc_id, track_id, range)
+            .await?;
+        Ok(link.map_err(|mut streamed| {
+            streamed.audio.reader = hold_permit(streamed.audio.reader, permit);
+            streamed
+        }))
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        let permit = self.limit.acquire().await?;
+        let link = self.inner.get_cover_link(album_id, disc_id).await?;
+        Ok(link.map_err(|reader| hold_permit(reader, permit)))
+    }
+
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        let permit = self.limit.acquire().await?;
+        let cover = self.inner.get_cover_validated(album_id, disc_id).await?;
+        Ok(cover.map_err(|(reader, validators)| (hold_permit(reader, permit), validators)))
+    }
+
+    async fn get_cover_thumbnail_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+        size: u32,
+    ) -> anni_provider::Result<Option<Result<String, ResourceReader>>> {
+        let permit = self.limit.acquire().await?;
+        let link = self
+            .inner
+            .get_cover_thumbnail_link(album_id, disc_id, size)
+            .await?;
+        Ok(link.map(|link| link.map_err(|reader| hold_permit(reader, permit))))
+    }
+
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        let _permit = self.limit.acquire().await?;
+        self.inner
+            .get_audio_details(album_id, disc_id, track_id)
+            .await
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        let _permit = self.limit.acquire().await?;
+        self.inner.exists(album_id, disc_id, track_id).await
+    }
+
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        let _permit = self.limit.acquire().await?;
+        self.inner.prepare_reload().await
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        let _permit = self.limit.acquire().await?;
+        self.inner.list_tracks(album_id).await
+    }
+
+    fn lists_tracks_cheaply(&self) -> bool {
+        self.inner.lists_tracks_cheaply()
+    }
+
+    fn album_titles(&self, album_id: &str) -> Option<AlbumTitles> {
+        self.inner.album_titles(album_id)
+    }
+}
+
+/// Keeps the album list of another provider in memory for `ttl`, or until the next reload.
+///
+/// A `ttl` of 0 lists the albums of the wrapped provider every time.
+///
+/// Reloads are what change the library etag, so a cached list never outlives the etag it was
+/// listed under.
+pub struct AlbumListCache<P> {
+    inner: P,
+    ttl: Duration,
+    /// held while the list is fetched, so that concurrent misses fetch it only once
+    albums: tokio::sync::Mutex<Option<(HashSet<String>, Instant)>>,
+}
+
+impl<P> AlbumListCache<P> {
+    pub fn new(inner: P, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            albums: Default::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: AnniProvider + Send + Sync> AnniProvider for AlbumListCache<P> {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        if self.ttl.is_zero() {
+            return self.inner.albums().await;
+        }
+        let mut cached = self.albums.lock().await;
+        match &*cached {
+            Some((albums, listed_at)) if listed_at.elapsed() < self.ttl => {}
+            _ => {
+                let albums = self.inner.albums().await?;
+                let albums = albums.into_iter().map(Cow::into_owned).collect();
+                *cached = Some((albums, Instant::now()));
+            }
+        }
+        let (albums, _) = cached.as_ref().unwrap();
+        Ok(albums.iter().cloned().map(Cow::Owned).collect())
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        self.inner
+            .get_audio(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        self.inner.get_cover(album_id, disc_id).await
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        *self.albums.get_mut() = None;
+        self.inner.reload().await
+    }
+}
+
+impl<P: AnniURLProvider + Send + Sync> AnniURLProvider for AlbumListCache<P> {
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        self.inner
+            .get_audio_link(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        self.inner
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        self.inner
+            .get_audio_link_details(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        self.inner.get_cover_link(album_id, disc_id).await
+    }
+
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        self.inner.get_cover_validated(album_id, disc_id).await
+    }
+
+    async fn get_cover_thumbnail_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+        size: u32,
+    ) -> anni_provider::Result<Option<Result<String, ResourceReader>>> {
+        self.inner
+            .get_cover_thumbnail_link(album_id, disc_id, size)
+            .await
+    }
+
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        self.inner
+            .get_audio_details(album_id, disc_id, track_id)
+            .await
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        self.inner.exists(album_id, disc_id, track_id).await
+    }
+
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        self.inner.prepare_reload().await
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        self.inner.list_tracks(album_id).await
+    }
+
+    fn lists_tracks_cheaply(&self) -> bool {
+        self.inner.lists_tracks_cheaply()
+    }
+
+    fn album_titles(&self, album_id: &str) -> Option<AlbumTitles> {
+        self.inner.album_titles(album_id)
+    }
+}
+
+/// Serves from a secondary provider, such as a mirror, when the primary one fails.
+///
+/// Only errors fall back: a file the primary provider reports missing is missing, and a file the
+/// secondary one reports missing after the primary failed is reported with the primary's error,
+/// as it may well exist there. Albums are listed from both, and albums only the secondary one
+/// listed are served from it directly.
+///
+/// Without a secondary provider, the primary one is served as it is.
+pub struct FallbackProvider<A, B> {
+    primary: A,
+    secondary: Option<B>,
+    /// albums only the secondary provider listed, as of the last listing of both
+    secondary_albums: RwLock<HashSet<String>>,
+    /// albums only the secondary provider listed while preparing a reload
+    prepared: Mutex<Option<HashSet<String>>>,
+}
+
+impl<A, B> FallbackProvider<A, B> {
+    pub fn new(primary: A, secondary: Option<B>) -> Self {
+        Self {
+            primary,
+            secondary,
+            secondary_albums: Default::default(),
+            prepared: Default::default(),
+        }
+    }
+
+    /// Awaits `secondary` for albums only the secondary provider has, and `primary` otherwise,
+    /// falling back to `secondary` if the primary provider failed.
+    async fn route<T>(
+        &self,
+        album_id: &str,
+        primary: impl Future<Output = anni_provider::Result<T>>,
+        secondary: Option<impl Future<Output = anni_provider::Result<T>>>,
+    ) -> anni_provider::Result<T> {
+        let secondary_only = self.secondary_albums.read().unwrap().contains(album_id);
+        match secondary {
+            Some(secondary) if secondary_only => secondary.await,
+            secondary => fall_back(primary, secondary).await,
+        }
+    }
+}
+
+/// Albums of `secondary` missing from `primary`.
+fn secondary_only<'a>(
+    primary: impl IntoIterator<Item = &'a str>,
+    secondary: impl IntoIterator<Item = &'a str>,
+) -> HashSet<String> {
+    let primary: HashSet<_> = primary.into_iter().collect();
+    secondary
+        .into_iter()
+        .filter(|album_id| !primary.contains(album_id))
+        .map(str::to_owned)
+        .collect()
+}
+
+fn is_missing(error: &ProviderError) -> bool {
+    matches!(
+        error,
+        ProviderError::FileNotFound | ProviderError::InvalidPath
+    )
+}
+
+/// Awaits `primary`, and `secondary` if the primary provider failed and there is a secondary one.
+async fn fall_back<T>(
+    primary: impl Future<Output = anni_provider::Result<T>>,
+    secondary: Option<impl Future<Output = anni_provider::Result<T>>>,
+) -> anni_provider::Result<T> {
+    let error = match primary.await {
+        Err(e) if !is_missing(&e) => e,
+        result => return result,
+    };
+    let Some(secondary) = secondary else {
+        return Err(error);
+    };
+    tracing::warn!(error = %error, "primary provider failed, trying the fallback");
+    match secondary.await {
+        Ok(value) => {
+            metrics::counter!("fallback_responses_total").increment(1);
+            Ok(value)
+        }
+        Err(e) => {
+            if !is_missing(&e) {
+                tracing::warn!(error = %e, "fallback provider failed as well");
+            }
+            Err(error)
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<A, B> AnniProvider for FallbackProvider<A, B>
+where
+    A: AnniProvider + Send + Sync,
+    B: AnniProvider + Send + Sync,
+{
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        let Some(secondary) = &self.secondary else {
+            return self.primary.albums().await;
+        };
+        match tokio::join!(self.primary.albums(), secondary.albums()) {
+            (Ok(mut albums), Ok(secondary)) => {
+                *self.secondary_albums.write().unwrap() = secondary_only(
+                    albums.iter().map(|album_id| album_id.as_ref()),
+                    secondary.iter().map(|album_id| album_id.as_ref()),
+                );
+                albums.extend(secondary);
+                Ok(albums)
+            }
+            (Ok(albums), Err(e)) => {
+                tracing::warn!(error = %e, "fallback provider failed to list albums");
+                Ok(albums)
+            }
+            (Err(e), Ok(albums)) => {
+                tracing::warn!(error = %e, "primary provider failed to list albums");
+                Ok(albums)
+            }
+            (Err(e), Err(_)) => Err(e),
+        }
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        self.route(
+            album_id,
+            self.primary.get_audio(album_id, disc_id, track_id, range),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.get_audio(album_id, disc_id, track_id, range)),
+        )
+        .await
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        self.route(
+            album_id,
+            self.primary.get_cover(album_id, disc_id),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.get_cover(album_id, disc_id)),
+        )
+        .await
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        let primary = self.primary.reload().await;
+        let secondary = match &mut self.secondary {
+            Some(secondary) => secondary.reload().await,
+            None => Ok(()),
+        };
+        if let Some(albums) = self.prepared.get_mut().unwrap().take() {
+            *self.secondary_albums.get_mut().unwrap() = albums;
+        }
+        primary.and(secondary)
+    }
+}
+
+impl<A, B> AnniURLProvider for FallbackProvider<A, B>
+where
+    A: AnniURLProvider + Send + Sync,
+    B: AnniURLProvider + Send + Sync,
+{
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        self.route(
+            album_id,
+            self.primary
+                .get_audio_link(album_id, disc_id, track_id, range),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.get_audio_link(album_id, disc_id, track_id, range)),
+        )
+        .await
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        self.route(
+            album_id,
+            self.primary
+                .get_audio_stream(album_id, disc_id, track_id, range),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.get_audio_stream(album_id, disc_id, track_id, range)),
+        )
+        .await
+    }
+
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        self.route(
+            album_id,
+            self.primary
+                .get_audio_link_details(album_id, disc_id, track_id, range),
+            self.secondary.as_ref().map(|secondary| {
+                secondary.get_audio_link_details(album_id, disc_id, track_id, range)
+            }),
+        )
+        .await
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        self.route(
+            album_id,
+            self.primary.get_cover_link(album_id, disc_id),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.get_cover_link(album_id, disc_id)),
+        )
+        .await
+    }
+
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        self.route(
+            album_id,
+            self.primary.get_cover_validated(album_id, disc_id),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.get_cover_validated(album_id, disc_id)),
+        )
+        .await
+    }
+
+    async fn get_cover_thumbnail_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+        size: u32,
+    ) -> anni_provider::Result<Option<Result<String, ResourceReader>>> {
+        self.route(
+            album_id,
+            self.primary
+                .get_cover_thumbnail_link(album_id, disc_id, size),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.get_cover_thumbnail_link(album_id, disc_id, size)),
+        )
+        .await
+    }
+
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        self.route(
+            album_id,
+            self.primary.get_audio_details(album_id, disc_id, track_id),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.get_audio_details(album_id, disc_id, track_id)),
+        )
+        .await
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        self.route(
+            album_id,
+            self.primary.exists(album_id, disc_id, track_id),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.exists(album_id, disc_id, track_id)),
+        )
+        .await
+    }
+
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        let Some(secondary) = &self.secondary else {
+            return self.primary.prepare_reload().await;
+        };
+        let (primary, secondary) =
+            tokio::join!(self.primary.prepare_reload(), secondary.prepare_reload());
+        let (mut albums, secondary) = (primary?, secondary?);
+        *self.prepared.lock().unwrap() = Some(secondary_only(
+            albums.iter().map(String::as_str),
+            secondary.iter().map(String::as_str),
+        ));
+        albums.extend(secondary);
+        Ok(albums)
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        self.route(
+            album_id,
+            self.primary.list_tracks(album_id),
+            self.secondary
+                .as_ref()
+                .map(|secondary| secondary.list_tracks(album_id)),
+        )
+        .await
+    }
+
+    fn lists_tracks_cheaply(&self) -> bool {
+        self.primary.lists_tracks_cheaply()
+    }
+
+    fn album_titles(&self, album_id: &str) -> Option<AlbumTitles> {
+        self.primary.album_titles(album_id).or_else(|| {
+            self.secondary
+                .as_ref()
+                .and_then(|secondary| secondary.album_titles(album_id))
+        })
+    }
+}
+
+/// Titles of an album and its tracks, as listed in a manifest.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AlbumTitles {
+    pub title: Option<String>,
+    /// titles of the tracks of each disc, in order
+    #[serde(default)]
+    pub discs: Vec<Vec<String>>,
+}
+
+impl AlbumTitles {
+    pub fn track(&self, disc_id: NonZeroU8, track_id: NonZeroU8) -> Option<&str> {
+        let disc = self.discs.get(usize::from(disc_id.get()) - 1)?;
+        disc.get(usize::from(track_id.get()) - 1)
+            .map(String::as_str)
+    }
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    albums: HashMap<String, AlbumTitles>,
+}
+
+/// Overlays album and track titles from a manifest file on another provider.
+///
+/// The manifest is a TOML file, or JSON if its name ends with `.json`, of the form
+///
+/// ```toml
+/// [albums.<album id>]
+/// title = "Album"
+/// discs = [["Track 1", "Track 2"], ["Track 1"]]
+/// ```
+///
+/// It is read again on every reload.
+pub struct ManifestProvider<P> {
+    inner: P,
+    /// `None` without a manifest, leaving titles to the wrapped provider
+    path: Option<PathBuf>,
+    albums: HashMap<String, AlbumTitles>,
+}
+
+impl<P> ManifestProvider<P> {
+    pub fn new(inner: P, path: PathBuf) -> std::io::Result<Self> {
+        let albums = parse_manifest(&path, &std::fs::read_to_string(&path)?)?;
+        Ok(Self {
+            inner,
+            path: Some(path),
+            albums,
+        })
+    }
+
+    /// Serves the titles of the wrapped provider as they are.
+    pub fn without_manifest(inner: P) -> Self {
+        Self {
+            inner,
+            path: None,
+            albums: HashMap::new(),
+        }
+    }
+}
+
+fn parse_manifest(
+    path: &std::path::Path,
+    manifest: &str,
+) -> std::io::Result<HashMap<String, AlbumTitles>> {
+    let manifest: Manifest = if path.extension().is_some_and(|e| e == "json") {
+        serde_json::from_str(manifest).map_err(std::io::Error::other)?
+    } else {
+        toml::from_str(manifest).map_err(std::io::Error::other)?
+    };
+    Ok(manifest.albums)
+}
+
+#[async_trait::async_trait]
+impl<P: AnniProvider + Send + Sync> AnniProvider for ManifestProvider<P> {
+    async fn albums(&self) -> anni_provider::Result<HashSet<Cow<str>>> {
+        self.inner.albums().await
+    }
+
+    async fn get_audio(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<AudioResourceReader> {
+        self.inner
+            .get_audio(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_cover(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ResourceReader> {
+        self.inner.get_cover(album_id, disc_id).await
+    }
+
+    async fn reload(&mut self) -> anni_provider::Result<()> {
+        if let Some(path) = &self.path {
+            // a manifest that can't be read keeps the previous one
+            let manifest = tokio::fs::read_to_string(path).await?;
+            self.albums = parse_manifest(path, &manifest)?;
+        }
+        self.inner.reload().await
+    }
+}
+
+impl<P: AnniURLProvider + Send + Sync> AnniURLProvider for ManifestProvider<P> {
+    async fn get_audio_link(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
+        self.inner
+            .get_audio_link(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<StreamedAudio> {
+        self.inner
+            .get_audio_stream(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> anni_provider::Result<LinkedAudio> {
+        self.inner
+            .get_audio_link_details(album_id, disc_id, track_id, range)
+            .await
+    }
+
+    async fn get_cover_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<Result<String, ResourceReader>> {
+        self.inner.get_cover_link(album_id, disc_id).await
+    }
+
+    async fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> anni_provider::Result<ValidatedCover> {
+        self.inner.get_cover_validated(album_id, disc_id).await
+    }
+
+    async fn get_cover_thumbnail_link(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+        size: u32,
+    ) -> anni_provider::Result<Option<Result<String, ResourceReader>>> {
+        self.inner
+            .get_cover_thumbnail_link(album_id, disc_id, size)
+            .await
+    }
+
+    async fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<AudioDetails> {
+        self.inner
+            .get_audio_details(album_id, disc_id, track_id)
+            .await
+    }
+
+    async fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> anni_provider::Result<bool> {
+        self.inner.exists(album_id, disc_id, track_id).await
+    }
+
+    async fn prepare_reload(&self) -> anni_provider::Result<HashSet<String>> {
+        self.inner.prepare_reload().await
+    }
+
+    async fn list_tracks(&self, album_id: &str) -> anni_provider::Result<Option<Vec<DiscTracks>>> {
+        self.inner.list_tracks(album_id).await
+    }
+
+    fn lists_tracks_cheaply(&self) -> bool {
+        self.inner.lists_tracks_cheaply()
+    }
+
+    fn album_titles(&self, album_id: &str) -> Option<AlbumTitles> {
+        if self.path.is_none() {
+            return self.inner.album_titles(album_id);
+        }
+        self.albums.get(album_id).cloned()
+    }
+}
+
+/// Parses the `Content-Range` header of a response, falling back to the full range.
+pub fn content_range_to_range(content_range: Option<&str>) -> Range {
+    match content_range {
+        Some(content_range) => {
+            // if content range header is invalid, return the full range
+            if content_range.len() <= 6 {
+                return Range::FULL;
+            }
+
+            // else, parse the range
+            // Content-Range: bytes 0-1023/10240
+            //                      | offset = 6
+            let content_range = &content_range[6..];
+            let (range, total) = content_range.split_once('/').unwrap_or((content_range, ""));
+            // an unknown total is sent as `*`, which fails to parse and becomes `None`
+            let total = total.parse().ok();
+
+            // Content-Range: bytes */10240
+            // is sent for unsatisfiable ranges and carries no range at all
+            if range == "*" {
+                return Range {
+                    start: 0,
+                    end: None,
+                    total,
+                };
+            }
+
+            let (from, to) = range.split_once('-').unwrap_or((range, ""));
+            Range {
+                start: from.parse().unwrap_or(0),
+                end: to.parse().ok(),
+                total,
+            }
+        }
+        None => Range::FULL,
+    }
+}
+
+fn to_io_error<T, E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+    r: Result<T, E>,
+) -> Result<T, std::io::Error> {
+    r.map_err(|e: E| std::io::Error::new(std::io::ErrorKind::Other, e))
+}
+
+/// Largest ID3v2 tag that is buffered in front of a FLAC header.
+const MAX_ID3_SIZE: usize = 16 * 1024 * 1024;
+
+/// Bytes in the header of an ID3v2 tag, which ends with the size of the rest of the tag.
+const ID3_HEADER_SIZE: usize = 10;
+
+/// Bytes taken by the ID3v2 tag whose header is at the start of `head`, including the header.
+///
+/// Returns `None` if `head` doesn't start with an ID3v2 header.
+fn id3_tag_size(head: &[u8]) -> Option<u64> {
+    if head.len() < ID3_HEADER_SIZE || !head.starts_with(b"ID3") {
+        return None;
+    }
+    // version (2 bytes), flags, then the size in 7 bit bytes
+    let size = head[6..10]
+        .iter()
+        .fold(0, |size, b| (size << 7) | (b & 0x7f) as u64);
+    let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(ID3_HEADER_SIZE as u64 + size + footer)
+}
+
+/// Reads the rest of the ID3v2 header that `magic`, the first bytes of a stream, starts.
+///
+/// Returns `None` without reading anything if there is no ID3v2 tag.
+async fn read_id3_header<R>(
+    reader: &mut R,
+    magic: [u8; 4],
+) -> std::io::Result<Option<[u8; ID3_HEADER_SIZE]>>
+where
+    R: AsyncRead + Unpin,
+{
+    if !magic.starts_with(b"ID3") {
+        return Ok(None);
+    }
+    let mut header = [0; ID3_HEADER_SIZE];
+    header[..4].copy_from_slice(&magic);
+    reader.read_exact(&mut header[4..]).await?;
+    Ok(Some(header))
+}
+
+/// [`Range::FLAC_HEADER`] moved past an ID3v2 tag of `tag_size` bytes.
+fn flac_header_after(tag_size: u64) -> Range {
+    Range {
+        start: Range::FLAC_HEADER.start + tag_size,
+        end: Range::FLAC_HEADER.end.map(|end| end + tag_size),
+        total: None,
+    }
+}
+
+/// Reads the STREAMINFO block at the start of a FLAC stream.
+///
+/// This is the only place audio is buffered: the bytes consumed here (the magic, the STREAMINFO
+/// block and an ID3v2 tag of at most [`MAX_ID3_SIZE`]) are kept in memory and chained back in
+/// front of `reader`, which is otherwise passed on untouched, so the returned reader yields the
+/// stream unchanged without holding more than the header.
+async fn read_header<R>(mut reader: R) -> anni_provider::Result<(BlockStreamInfo, ResourceReader)>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (info, header) = read_stream_info(&mut reader).await?;
+    Ok((info, Box::pin(Cursor::new(header).chain(reader))))
+}
+
+/// Reads the STREAMINFO block from the start of FLAC audio, returning it with the bytes read.
+async fn read_stream_info<R>(reader: &mut R) -> anni_provider::Result<(BlockStreamInfo, Vec<u8>)>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let mut header = Cursor::new(Vec::with_capacity(4 + 4 + 34));
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).await?;
+    // some taggers prepend an ID3v2 tag, which is skipped but kept in the stream
+    if let Some(tag_header) = read_id3_header(reader, magic).await? {
+        let size = id3_tag_size(&tag_header).ok_or(ProviderError::GeneralError)? as usize;
+        if size > ID3_HEADER_SIZE + MAX_ID3_SIZE {
+            return Err(ProviderError::GeneralError);
+        }
+
+        let mut tag = vec![0; size - ID3_HEADER_SIZE];
+        reader.read_exact(&mut tag).await?;
+        header.write_all(&tag_header).await?;
+        header.write_all(&tag).await?;
+
+        reader.read_exact(&mut magic).await?;
+    }
+    if &magic != b"fLaC" {
+        return Err(ProviderError::GeneralError);
+    }
+
+    let block_header = reader.read_u32().await?;
+    // the first metadata block must be STREAMINFO, whose type is 0
+    if (block_header >> 24) & 0x7f != 0 {
+        return Err(ProviderError::GeneralError);
+    }
+    let info = BlockStreamInfo::from_async_reader(reader).await?;
+
+    header.write_all(&magic).await?;
+    header.write_u32(block_header).await?;
+    info.write_to(&mut header)?;
+
+    Ok((info, header.into_inner()))
+}
+
+/// Bytes at the start of a FLAC file fetched for its tags.
+///
+/// Taggers usually write VORBIS_COMMENT ahead of embedded pictures, so this is enough for the tags
+/// without fetching a cover along with them.
+pub const MAX_METADATA_SIZE: u64 = 256 * 1024;
+
+/// Tags of a track, read from the VORBIS_COMMENT block of a FLAC file.
+#[derive(Debug, Default, Serialize)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+}
+
+/// Reads the tags from the metadata blocks at the start of a FLAC stream.
+///
+/// Blocks before VORBIS_COMMENT are skipped without being buffered, and the rest of the stream
+/// is not read. Files without a VORBIS_COMMENT block have no tags.
+pub async fn read_tags<R>(mut reader: R) -> anni_provider::Result<TrackTags>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).await?;
+    if let Some(tag_header) = read_id3_header(&mut reader, magic).await? {
+        let size = id3_tag_size(&tag_header).ok_or(ProviderError::GeneralError)?;
+        skip(&mut reader, size - ID3_HEADER_SIZE as u64).await?;
+        reader.read_exact(&mut magic).await?;
+    }
+    if &magic != b"fLaC" {
+        return Err(ProviderError::GeneralError);
+    }
+
+    loop {
+        let block_header = reader.read_u32().await?;
+        let last = block_header & 0x8000_0000 != 0;
+        let length = block_header & 0x00ff_ffff;
+        if (block_header >> 24) & 0x7f == 4 {
+            if u64::from(length) > MAX_METADATA_SIZE {
+                return Err(ProviderError::GeneralError);
+            }
+            let mut block = vec![0; length as usize];
+            reader.read_exact(&mut block).await?;
+            return parse_vorbis_comment(&block).ok_or(ProviderError::GeneralError);
+        }
+        if last {
+            return Ok(TrackTags::default());
+        }
+        skip(&mut reader, length.into()).await?;
+    }
+}
+
+async fn skip<R: AsyncRead + Unpin>(reader: &mut R, len: u64) -> std::io::Result<()> {
+    let skipped = tokio::io::copy(&mut reader.take(len), &mut tokio::io::sink()).await?;
+    if skipped < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// Parses a VORBIS_COMMENT block, whose fields are little endian unlike the rest of FLAC.
+fn parse_vorbis_comment(mut block: &[u8]) -> Option<TrackTags> {
+    fn next<'a>(block: &mut &'a [u8]) -> Option<&'a [u8]> {
+        let len = u32::from_le_bytes(block.get(..4)?.try_into().ok()?) as usize;
+        let value = block.get(4..4 + len)?;
+        *block = &block[4 + len..];
+        Some(value)
+    }
+
+    let _vendor = next(&mut block)?;
+    let count = u32::from_le_bytes(block.get(..4)?.try_into().ok()?);
+    block = &block[4..];
+
+    let mut tags = TrackTags::default();
+    for _ in 0..count {
+        let comment = String::from_utf8_lossy(next(&mut block)?);
+        let Some((key, value)) = comment.split_once('=') else {
+            continue;
+        };
+        // numbers may be written as `3/12`
+        let number = || value.split('/').next()?.trim().parse().ok();
+        match key.to_ascii_uppercase().as_str() {
+            "TITLE" => tags.title = Some(value.to_owned()),
+            "ARTIST" => tags.artist = Some(value.to_owned()),
+            "ALBUM" => tags.album = Some(value.to_owned()),
+            "TRACKNUMBER" => tags.track_number = number(),
+            "DISCNUMBER" => tags.disc_number = number(),
+            _ => {}
+        }
+    }
+    Some(tags)
+}
+
+/// Reads the stream info of a FLAC stream if `range` covers its header, buffering only the
+/// header.
+async fn probe_stream(
+    reader: ResourceReader,
+    range: Range,
+) -> anni_provider::Result<(Option<BlockStreamInfo>, ResourceReader)> {
+    if !range.contains_flac_header() {
+        return Ok((None, reader));
+    }
+
+    let (info, reader) = read_header(reader).await?;
+    Ok((Some(info), reader))
+}
+
+/// Computes the duration of a stream in seconds, rounded to the nearest one.
+fn duration_secs(info: &BlockStreamInfo) -> u64 {
+    (duration_millis(info) + 500) / 1000
+}
+
+/// Computes the duration of a stream in milliseconds, rounded to the nearest one.
+///
+/// Returns 0 if the duration is unknown.
+fn duration_millis(info: &BlockStreamInfo) -> u64 {
+    let sample_rate = info.sample_rate as u128;
+    if sample_rate == 0 {
+        return 0;
+    }
+    // widen so that long streams at high sample rates don't overflow
+    ((info.total_samples as u128 * 1000 + sample_rate / 2) / sample_rate) as u64
+}
+
+/// Layout of track files in a provider, such as `{album}/Disc {disc}/{track:02}`.
+///
+/// `{album}`, `{disc}` and `{track}` are replaced with the ids of a track, and a width such as
+/// `{track:02}` pads the number with zeros. Covers are looked up next to the tracks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PathTemplate {
+    /// parts of each `/` separated segment
+    segments: Vec<Vec<TemplatePart>>,
+}
+
+#[derive(Debug, Clone)]
+enum TemplatePart {
+    Literal(String),
+    Album,
+    Disc(usize),
+    Track(usize),
+}
+
+impl TemplatePart {
+    fn is_album(&self) -> bool {
+        matches!(self, Self::Album)
+    }
+
+    fn is_track(&self) -> bool {
+        matches!(self, Self::Track(_))
+    }
+}
 
-    async fn get_cover(
-        &self,
-        _album_id: &str,
-        _disc_id: Option<NonZeroU8>,
-    ) -> anni_provider::Result<ResourceReader> {
-        unimplemented!()
+impl PathTemplate {
+    pub const DEFAULT: &'static str = "{album}/{disc}/{track}";
+}
+
+/// Tracks are at the rendered template, covers next to them and in the directory containing
+/// `{album}`.
+impl PathMapper for PathTemplate {
+    fn disc_dir(&self, album_id: &str, disc_id: NonZeroU8) -> String {
+        let dir = &self.segments[..self.segments.len() - 1];
+        render_segments(dir, album_id, disc_id, NonZeroU8::MIN)
     }
 
-    async fn reload(&mut self) -> anni_provider::Result<()> {
-        Ok(())
+    fn track_path(&self, album_id: &str, disc_id: NonZeroU8, track_id: NonZeroU8) -> String {
+        render_segments(&self.segments, album_id, disc_id, track_id)
+    }
+
+    fn album_dir(&self, album_id: &str) -> String {
+        let end = self
+            .segments
+            .iter()
+            .position(|segment| segment.iter().any(TemplatePart::is_album))
+            .map_or(0, |i| i + 1);
+        render_segments(
+            &self.segments[..end],
+            album_id,
+            NonZeroU8::MIN,
+            NonZeroU8::MIN,
+        )
+    }
+
+    fn track_of(&self, album_id: &str, disc_id: NonZeroU8, name: &str) -> Option<NonZeroU8> {
+        let file = self.segments.last()?;
+        let track = file.iter().position(TemplatePart::is_track)?;
+        let prefix = render_parts(&file[..track], album_id, disc_id, NonZeroU8::MIN);
+        let suffix = render_parts(&file[track + 1..], album_id, disc_id, NonZeroU8::MIN);
+        parse_id(name.strip_prefix(&prefix)?.strip_suffix(&suffix)?)
+    }
+
+    fn has_disc_dir(&self) -> bool {
+        let dir = &self.segments[..self.segments.len() - 1];
+        dir.iter()
+            .flatten()
+            .any(|part| matches!(part, TemplatePart::Disc(_)))
     }
 }
 
-impl AnniURLProvider for SeafileProvider {
-    async fn get_audio_link(
-        &self,
-        album_id: &str,
-        disc_id: NonZeroU8,
-        track_id: NonZeroU8,
-        _range: Range,
-    ) -> anni_provider::Result<Result<String, AudioResourceReader>> {
-        Ok(Ok(self
-            .get_download_link(format!("{album_id}/{disc_id}/{track_id}.flac"))
-            .await?))
+impl Default for PathTemplate {
+    fn default() -> Self {
+        Self::DEFAULT.parse().unwrap()
     }
+}
 
-    async fn get_cover_link(
-        &self,
-        album_id: &str,
-        disc_id: Option<NonZeroU8>,
-    ) -> anni_provider::Result<Result<String, ResourceReader>> {
-        Ok(Ok(self
-            .get_download_link(format!(
-                "{album_id}/{}/cover.jpg",
-                disc_id.map(|id| id.get()).unwrap_or(1)
-            ))
-            .await?))
+impl FromStr for PathTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let segments = template
+            .split('/')
+            .map(parse_template_segment)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (file, dir) = segments
+            .split_last()
+            .expect("split yields at least one segment");
+        if !file.iter().any(TemplatePart::is_track) {
+            return Err(format!(
+                "`{{track}}` must be in the file name of `{template}`"
+            ));
+        }
+        if dir.iter().flatten().any(TemplatePart::is_track) {
+            return Err(format!(
+                "`{{track}}` must not be in a directory of `{template}`"
+            ));
+        }
+        if !dir.iter().flatten().any(TemplatePart::is_album) {
+            return Err(format!(
+                "`{{album}}` must be in a directory of `{template}`"
+            ));
+        }
+        Ok(Self { segments })
     }
 }
 
-fn content_range_to_range(content_range: Option<&str>) -> Range {
-    match content_range {
-        Some(content_range) => {
-            // if content range header is invalid, return the full range
-            if content_range.len() <= 6 {
-                return Range::FULL;
-            }
+impl TryFrom<String> for PathTemplate {
+    type Error = String;
 
-            // else, parse the range
-            // Content-Range: bytes 0-1023/10240
-            //                      | offset = 6
-            let content_range = &content_range[6..];
-            let (from, content_range) =
-                content_range.split_once('-').unwrap_or((content_range, ""));
-            let (to, total) = content_range.split_once('/').unwrap_or((content_range, ""));
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        template.parse()
+    }
+}
 
-            Range {
-                start: from.parse().unwrap_or(0),
-                end: to.parse().ok(),
-                total: total.parse().ok(),
-            }
+fn parse_template_segment(mut segment: &str) -> Result<Vec<TemplatePart>, String> {
+    let mut parts = Vec::new();
+    while let Some(start) = segment.find('{') {
+        if start > 0 {
+            parts.push(TemplatePart::Literal(segment[..start].to_owned()));
         }
-        None => Range::FULL,
+        let end = segment[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed placeholder in `{segment}`"))?
+            + start;
+        let placeholder = &segment[start + 1..end];
+        let (name, width) = match placeholder.split_once(':') {
+            Some((name, width)) => (
+                name,
+                width
+                    .parse()
+                    .map_err(|_| format!("invalid width in `{{{placeholder}}}`"))?,
+            ),
+            None => (placeholder, 0),
+        };
+        parts.push(match name {
+            "album" => TemplatePart::Album,
+            "disc" => TemplatePart::Disc(width),
+            "track" => TemplatePart::Track(width),
+            _ => return Err(format!("unknown placeholder `{{{placeholder}}}`")),
+        });
+        segment = &segment[end + 1..];
     }
+    if !segment.is_empty() {
+        parts.push(TemplatePart::Literal(segment.to_owned()));
+    }
+    Ok(parts)
 }
 
-fn to_io_error<T, E: Into<Box<dyn std::error::Error + Send + Sync>>>(
-    r: Result<T, E>,
-) -> Result<T, std::io::Error> {
-    r.map_err(|e: E| std::io::Error::new(std::io::ErrorKind::Other, e))
+fn render_segments(
+    segments: &[Vec<TemplatePart>],
+    album_id: &str,
+    disc_id: NonZeroU8,
+    track_id: NonZeroU8,
+) -> String {
+    let segments: Vec<_> = segments
+        .iter()
+        .map(|segment| render_parts(segment, album_id, disc_id, track_id))
+        .collect();
+    segments.join("/")
 }
 
-async fn read_header<R>(mut reader: R) -> anni_provider::Result<(BlockStreamInfo, ResourceReader)>
+fn render_parts(
+    parts: &[TemplatePart],
+    album_id: &str,
+    disc_id: NonZeroU8,
+    track_id: NonZeroU8,
+) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            TemplatePart::Literal(literal) => literal.clone(),
+            TemplatePart::Album => album_id.to_owned(),
+            TemplatePart::Disc(width) => format!("{:0width$}", disc_id.get()),
+            TemplatePart::Track(width) => format!("{:0width$}", track_id.get()),
+        })
+        .collect()
+}
+
+/// Lists the tracks of an album by listing disc directories in order until one is missing.
+///
+/// `list_dir` returns the file names in a directory, which are matched against the layout.
+async fn probe_discs<F, Fut>(
+    paths: &dyn PathMapper,
+    album_id: &str,
+    mut list_dir: F,
+) -> anni_provider::Result<Vec<DiscTracks>>
 where
-    R: AsyncRead + Unpin + Send + 'static,
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = anni_provider::Result<Vec<String>>>,
 {
-    let first = reader.read_u32().await.unwrap();
-    let second = reader.read_u32().await.unwrap();
-    let info = BlockStreamInfo::from_async_reader(&mut reader).await?;
+    // all discs share one directory if it isn't named after them
+    let last_disc = if paths.has_disc_dir() { u8::MAX } else { 1 };
 
-    let mut header = Cursor::new(Vec::with_capacity(4 + 4 + 34));
-    header.write_u32(first).await.unwrap();
-    header.write_u32(second).await.unwrap();
-    info.write_to(&mut header).unwrap();
-    header.set_position(0);
+    let mut discs = Vec::new();
+    for disc_id in (1..=last_disc).filter_map(NonZeroU8::new) {
+        let names = match list_dir(paths.disc_dir(album_id, disc_id)).await {
+            Ok(names) => names,
+            Err(ProviderError::FileNotFound) => break,
+            Err(e) => return Err(e),
+        };
+        let mut tracks: Vec<_> = names
+            .iter()
+            .filter_map(|name| paths.track_of(album_id, disc_id, name))
+            .collect();
+        if tracks.is_empty() {
+            continue;
+        }
+        tracks.sort_unstable();
+        tracks.dedup();
+        discs.push(DiscTracks {
+            disc: disc_id,
+            tracks,
+        });
+    }
+    Ok(discs)
+}
+
+/// Tries `find` with each extension in order, returning what it found first along with the
+/// extension.
+///
+/// Extensions a track is missing with are skipped and other errors are returned right away, a
+/// track missing with all of them is missing.
+async fn find_extension<'e, T, F, Fut>(
+    extensions: &'e [String],
+    mut find: F,
+) -> anni_provider::Result<(T, &'e str)>
+where
+    F: FnMut(&'e str) -> Fut,
+    Fut: Future<Output = anni_provider::Result<T>>,
+{
+    for extension in extensions {
+        match find(extension).await {
+            Ok(found) => return Ok((found, extension)),
+            Err(ProviderError::FileNotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(ProviderError::FileNotFound)
+}
 
-    Ok((info, Box::pin(header.chain(reader))))
+/// Strips one of `extensions` from a file name, returning `None` if it has none of them.
+fn strip_extension<'a>(name: &'a str, extensions: &[String]) -> Option<&'a str> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    extensions.iter().any(|e| e == extension).then_some(stem)
 }
 
-pub(crate) async fn read_duration(
-    reader: ResourceReader,
+/// Extension of a track stored at `path` as it is, which is FLAC unless the file name has one.
+fn extension_of(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.')
+        .map_or("flac", |(_, extension)| extension)
+}
+
+/// Retry policy for idempotent upstream requests.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    /// how many times a request is retried after the first attempt
+    pub retries: u32,
+    /// delay before the first retry, doubled on every following one
+    pub base_delay: Duration,
+}
+
+impl Retry {
+    pub const NONE: Self = Self {
+        retries: 0,
+        base_delay: Duration::ZERO,
+    };
+
+    /// Sends `req`, retrying on network errors and 5xx responses.
+    ///
+    /// Other responses, including 404, are returned as is.
+    pub async fn send(&self, req: reqwest::RequestBuilder) -> reqwest::Result<Response> {
+        let mut attempt = 0;
+        loop {
+            // only requests with streaming bodies can't be cloned, and we never send those
+            let Some(this) = req.try_clone() else {
+                return req.send().await;
+            };
+            let result = this.send().await;
+            let retryable = match &result {
+                Ok(resp) => resp.status().is_server_error(),
+                Err(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            };
+            if !retryable || attempt >= self.retries {
+                return result;
+            }
+
+            tokio::time::sleep(self.delay(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay.saturating_mul(1 << attempt.min(16));
+        // jitter between half and the full delay so that clients don't retry in lockstep
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+}
+
+/// Returns the size of the response body.
+///
+/// Chunked responses may omit `Content-Length`, in which case the total from `Content-Range` is used.
+fn response_size(resp: &Response) -> anni_provider::Result<usize> {
+    resp.content_length()
+        .or_else(|| {
+            content_range_to_range(
+                resp.headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok()),
+            )
+            .total
+        })
+        .map(|size| size as usize)
+        .ok_or(ProviderError::GeneralError)
+}
+
+/// Sends an audio request, asking only for `range` of the file.
+///
+/// The returned range is the one the upstream sent, which is the whole file if it ignored the
+/// request for `range`, and the size is that of the whole file. The stream info is only read for
+/// FLAC files with `probe_duration` set whose header is sent, the duration is reported as 0
+/// otherwise.
+async fn fetch_audio(
+    req: reqwest::RequestBuilder,
+    extension: &str,
     range: Range,
-) -> anni_provider::Result<(u64, ResourceReader)> {
-    if !range.contains_flac_header() {
-        return Ok((0, reader));
+    retry: &Retry,
+    probe_duration: bool,
+) -> anni_provider::Result<StreamedAudio> {
+    let req = match range.to_range_header() {
+        Some(h) => req.header(RANGE, h),
+        None => req,
+    };
+    let start = Instant::now();
+    let resp = retry.send(req).await?;
+    metrics::histogram!("upstream_request_duration_seconds", "operation" => "audio")
+        .record(start.elapsed().as_secs_f64());
+    let resp = check_audio_status(resp)?;
+    let (size, range) = received_range(&resp)?;
+    let (info, reader) = match extension {
+        "flac" if probe_duration => probe_stream(read_body(resp), range).await?,
+        _ => (None, read_body(resp)),
+    };
+    Ok(StreamedAudio {
+        audio: AudioResourceReader {
+            info: AudioInfo {
+                extension: extension.to_owned(),
+                size,
+                duration: info.as_ref().map_or(0, duration_secs),
+            },
+            range,
+            reader,
+        },
+        stream: info.as_ref().map(StreamInfo::from),
+    })
+}
+
+/// Returns the size of the whole file and the range of it a successful response holds.
+fn received_range(resp: &Response) -> anni_provider::Result<(usize, Range)> {
+    if resp.status() == StatusCode::PARTIAL_CONTENT {
+        let received = content_range_to_range(
+            resp.headers()
+                .get(CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok()),
+        );
+        let total = received.total.ok_or(ProviderError::GeneralError)?;
+        return Ok((total as usize, received));
     }
 
-    let (info, reader) = read_header(reader).await?;
-    let duration = info.total_samples / info.sample_rate as u64;
-    Ok((duration, reader))
+    // the range was ignored, so the body is the whole file
+    let size = response_size(resp)?;
+    let range = Range {
+        start: 0,
+        end: (size as u64).checked_sub(1),
+        total: Some(size as u64),
+    };
+    Ok((size, range))
+}
+
+/// Turns error responses to audio requests into errors, so that their bodies aren't streamed as
+/// audio.
+fn check_audio_status(resp: Response) -> anni_provider::Result<Response> {
+    match resp.status() {
+        StatusCode::NOT_FOUND => Err(ProviderError::FileNotFound),
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            // answered with `Content-Range: bytes */{size}`
+            let size = content_range_to_range(
+                resp.headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok()),
+            )
+            .total
+            .unwrap_or(0);
+            Err(std::io::Error::other(RangeNotSatisfiable { size }).into())
+        }
+        _ => Ok(resp.error_for_status()?),
+    }
+}
+
+/// Reads the size of a track, and the stream info of FLAC files, with ranged requests for just
+/// the header.
+///
+/// A FLAC file with an ID3v2 tag takes a second request, as the tag is skipped by its size.
+async fn fetch_details(
+    client: &reqwest::Client,
+    url: &str,
+    extension: &str,
+    retry: &Retry,
+) -> anni_provider::Result<AudioDetails> {
+    if extension != "flac" {
+        let first_byte = Range {
+            start: 0,
+            end: Some(0),
+            total: None,
+        };
+        let (_, size) = fetch_head(client, url, first_byte, retry).await?;
+        return Ok(AudioDetails {
+            info: AudioInfo {
+                extension: extension.to_owned(),
+                size,
+                duration: 0,
+            },
+            stream: None,
+        });
+    }
+
+    let (mut head, size) = fetch_head(client, url, Range::FLAC_HEADER, retry).await?;
+    if let Some(tag_size) = id3_tag_size(&head) {
+        (head, _) = fetch_head(client, url, flac_header_after(tag_size), retry).await?;
+    }
+    let (info, _) = read_header(Cursor::new(head)).await?;
+    Ok(AudioDetails {
+        info: AudioInfo {
+            extension: extension.to_owned(),
+            size,
+            // round to the nearest second
+            duration: (duration_millis(&info) + 500) / 1000,
+        },
+        stream: Some(StreamInfo::from(&info)),
+    })
+}
+
+/// Fetches `range` of `url`, which must have an end, along with the size of the whole file.
+///
+/// If the upstream ignores the range, only the bytes asked for are read of the full response.
+async fn fetch_head(
+    client: &reqwest::Client,
+    url: &str,
+    range: Range,
+    retry: &Retry,
+) -> anni_provider::Result<(Vec<u8>, usize)> {
+    let start = range.start;
+    let end = range.end.expect("header ranges have an end");
+    let len = end + 1 - start;
+    let req = client
+        .get(url)
+        .header(RANGE, format!("bytes={start}-{end}"));
+    let begin = Instant::now();
+    let resp = retry.send(req).await?;
+    metrics::histogram!("upstream_request_duration_seconds", "operation" => "audio_header")
+        .record(begin.elapsed().as_secs_f64());
+    let resp = check_audio_status(resp)?;
+
+    let partial = resp.status() == StatusCode::PARTIAL_CONTENT;
+    let size = if partial {
+        content_range_to_range(
+            resp.headers()
+                .get(CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok()),
+        )
+        .total
+        .map(|total| total as usize)
+        .ok_or(ProviderError::GeneralError)?
+    } else {
+        response_size(&resp)?
+    };
+    let mut head = Vec::with_capacity(len as usize);
+    let mut body = read_body(resp);
+    if !partial {
+        // the range was ignored, so the body starts at the beginning of the file
+        skip(&mut body, start).await?;
+    }
+    body.take(len).read_to_end(&mut head).await?;
+    Ok((head, size))
+}
+
+/// Splits large audio requests into several ranged requests sent at once.
+#[derive(Debug, Clone, Copy)]
+pub struct ParallelFetch {
+    /// how many chunks are requested at once
+    pub concurrency: usize,
+    /// bytes asked for by each request
+    pub chunk_size: u64,
 }
 
-async fn read_response(resp: Response) -> anni_provider::Result<(u64, ResourceReader)> {
-    let range = content_range_to_range(
+/// Like [`fetch_audio`], but fetches `range` of `url` in chunks of `parallel.chunk_size`.
+///
+/// The first chunk is streamed as it arrives, the rest are fetched by a background task at most
+/// `parallel.concurrency` at a time. Completed chunks are passed on in order through a channel
+/// that holds as many chunks as are fetched at once, so the task stops fetching when the client
+/// doesn't keep up and gives up when the response is dropped.
+///
+/// Falls back to a single request if the upstream ignores the range.
+async fn fetch_audio_parallel(
+    client: &reqwest::Client,
+    url: String,
+    extension: &str,
+    range: Range,
+    retry: Retry,
+    probe_duration: bool,
+    parallel: ParallelFetch,
+) -> anni_provider::Result<StreamedAudio> {
+    let first_end = range.start + parallel.chunk_size - 1;
+    let first_end = range.end.map_or(first_end, |end| end.min(first_end));
+    let start = Instant::now();
+    let resp = retry
+        .send(
+            client
+                .get(&url)
+                .header(RANGE, format!("bytes={}-{first_end}", range.start)),
+        )
+        .await?;
+    metrics::histogram!("upstream_request_duration_seconds", "operation" => "audio")
+        .record(start.elapsed().as_secs_f64());
+
+    let received = content_range_to_range(
         resp.headers()
             .get(CONTENT_RANGE)
             .and_then(|v| v.to_str().ok()),
     );
-    let reader = StreamReader::new(resp.bytes_stream().map(to_io_error));
-    read_duration(Box::pin(reader), range).await
+    let (Some(received_end), Some(total), StatusCode::PARTIAL_CONTENT) =
+        (received.end, received.total, resp.status())
+    else {
+        // the whole file was sent, or it's an error that fetch_audio reports
+        return fetch_audio(client.get(url), extension, range, &retry, probe_duration).await;
+    };
+
+    let end = range.end.map_or(total - 1, |end| end.min(total - 1));
+    let (tx, mut rx) = tokio::sync::mpsc::channel(parallel.concurrency);
+    let chunks = (received_end + 1..=end)
+        .step_by(parallel.chunk_size as usize)
+        .map(move |start| (start, (start + parallel.chunk_size - 1).min(end)));
+    let client = client.clone();
+    tokio::spawn(async move {
+        let mut chunks = futures_util::stream::iter(chunks)
+            .map(|(start, end)| fetch_chunk(&client, &url, start, end, retry))
+            .buffered(parallel.concurrency);
+        while let Some(chunk) = chunks.next().await {
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    let rest = futures_util::stream::poll_fn(move |cx| rx.poll_recv(cx));
+    let reader: ResourceReader = Box::pin(StreamReader::new(
+        resp.bytes_stream().map(to_io_error).chain(rest),
+    ));
+
+    let (info, reader) = match extension {
+        "flac" if probe_duration => probe_stream(reader, received).await?,
+        _ => (None, reader),
+    };
+    Ok(StreamedAudio {
+        audio: AudioResourceReader {
+            info: AudioInfo {
+                extension: extension.to_owned(),
+                size: total as usize,
+                duration: info.as_ref().map_or(0, duration_secs),
+            },
+            range: Range {
+                start: received.start,
+                end: Some(end),
+                total: Some(total),
+            },
+            reader,
+        },
+        stream: info.as_ref().map(StreamInfo::from),
+    })
+}
+
+/// Fetches bytes `start..=end` of `url` as one chunk.
+async fn fetch_chunk(
+    client: &reqwest::Client,
+    url: &str,
+    start: u64,
+    end: u64,
+    retry: Retry,
+) -> std::io::Result<Bytes> {
+    let resp = retry
+        .send(
+            client
+                .get(url)
+                .header(RANGE, format!("bytes={start}-{end}")),
+        )
+        .await
+        .map_err(std::io::Error::other)?;
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(std::io::Error::other(format!(
+            "upstream answered chunk {start}-{end} with {}",
+            resp.status()
+        )));
+    }
+    let chunk = resp.bytes().await.map_err(std::io::Error::other)?;
+    if chunk.len() as u64 != end + 1 - start {
+        return Err(std::io::Error::other(format!(
+            "upstream sent {} bytes for chunk {start}-{end}",
+            chunk.len()
+        )));
+    }
+    Ok(chunk)
+}
+
+/// Longest part of an upstream error body that is logged.
+const MAX_BODY_EXCERPT: usize = 512;
+
+/// Whether a response is an html page, such as an error page of a reverse proxy.
+fn is_html(resp: &Response) -> bool {
+    resp.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim_start().starts_with("text/html"))
+}
+
+/// Cuts a response body short for logging.
+fn body_excerpt(body: &str) -> &str {
+    match body.char_indices().nth(MAX_BODY_EXCERPT) {
+        Some((end, _)) => &body[..end],
+        None => body,
+    }
+}
+
+/// Ends a reader once it has yielded `max` bytes, however much more the upstream would send.
+pub fn limit_reader(reader: ResourceReader, max: u64) -> ResourceReader {
+    Box::pin(reader.take(max))
+}
+
+/// Streams a response body chunk by chunk as it arrives.
+fn read_body(resp: Response) -> ResourceReader {
+    Box::pin(StreamReader::new(resp.bytes_stream().map(to_io_error)))
+}
+
+/// Validators an upstream sent with a file, for answering conditional requests.
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Takes the `ETag` and `Last-Modified` headers of a response.
+    fn of(resp: &Response) -> Self {
+        let header = |name| resp.headers().get(name).and_then(|v| v.to_str().ok());
+        Self {
+            etag: header(ETAG).map(String::from),
+            last_modified: header(LAST_MODIFIED).and_then(|v| httpdate::parse_http_date(v).ok()),
+        }
+    }
+}
+
+/// A link to a cover, or the cover itself with its validators.
+pub type ValidatedCover = Result<String, (ResourceReader, Validators)>;
+
+/// A link to a track along with its details, or the audio itself.
+pub type LinkedAudio = Result<(String, AudioDetails), StreamedAudio>;
+
+/// The requested range starts beyond the end of a file of `size` bytes.
+#[derive(Debug)]
+pub struct RangeNotSatisfiable {
+    pub size: u64,
+}
+
+impl Display for RangeNotSatisfiable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "range starts beyond the end of the file of {} bytes",
+            self.size
+        )
+    }
+}
+
+impl std::error::Error for RangeNotSatisfiable {}
+
+fn handle_io_error(e: std::io::Error) -> anni_provider::ProviderError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => anni_provider::ProviderError::FileNotFound,
+        _ => e.into(),
+    }
 }
 
 fn handle_dav_error(e: reqwest_dav::Error) -> anni_provider::ProviderError {
+    use reqwest_dav::{DecodeError, ServerError, StatusMismatchedError};
+
     match e {
         reqwest_dav::Error::Reqwest(e) => e.into(),
+        reqwest_dav::Error::Decode(DecodeError::StatusMismatched(StatusMismatchedError {
+            response_code: 404,
+            ..
+        }))
+        | reqwest_dav::Error::Decode(DecodeError::Server(ServerError {
+            response_code: 404, ..
+        })) => anni_provider::ProviderError::FileNotFound,
         _ => anni_provider::ProviderError::GeneralError,
     }
 }
 
+/// Stream properties decoded from a FLAC header.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamInfo {
+    pub sample_rate: u32,
+    pub bits_per_sample: u8,
+    pub channels: u8,
+    pub duration_millis: u64,
+}
+
+impl From<&BlockStreamInfo> for StreamInfo {
+    fn from(info: &BlockStreamInfo) -> Self {
+        Self {
+            sample_rate: info.sample_rate,
+            bits_per_sample: info.bits_per_sample,
+            channels: info.channels,
+            duration_millis: duration_millis(info),
+        }
+    }
+}
+
+/// Quality of a track for `X-Audio-Quality`, judged by its format and, for lossless formats,
+/// its stream properties.
+///
+/// Lossless audio beyond CD quality, with more than 16 bits or more than 48 kHz, is
+/// `lossless-hq`. Without stream properties, lossless audio is reported as plain `lossless`.
+pub fn audio_quality(extension: &str, stream: Option<&StreamInfo>) -> &'static str {
+    match extension.to_ascii_lowercase().as_str() {
+        "flac" | "wav" => match stream {
+            Some(stream) if stream.bits_per_sample > 16 || stream.sample_rate > 48000 => {
+                "lossless-hq"
+            }
+            _ => "lossless",
+        },
+        "mp3" | "opus" | "ogg" | "aac" | "m4a" => "lossy",
+        _ => "unknown",
+    }
+}
+
+pub struct AudioDetails {
+    pub info: AudioInfo,
+    /// only available for FLAC files
+    pub stream: Option<StreamInfo>,
+}
+
+/// Audio streamed by the server, along with the stream info read on the way.
+pub struct StreamedAudio {
+    pub audio: AudioResourceReader,
+    /// only available for FLAC files whose header was read
+    pub stream: Option<StreamInfo>,
+}
+
+impl From<AudioResourceReader> for StreamedAudio {
+    fn from(audio: AudioResourceReader) -> Self {
+        Self {
+            audio,
+            stream: None,
+        }
+    }
+}
+
+/// Tracks present on a disc.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscTracks {
+    pub disc: NonZeroU8,
+    pub tracks: Vec<NonZeroU8>,
+}
+
 pub trait AnniURLProvider: AnniProvider {
     fn get_audio_link(
         &self,
@@ -318,8 +5037,8 @@ pub trait AnniURLProvider: AnniProvider {
         disc_id: NonZeroU8,
         track_id: NonZeroU8,
         range: Range,
-    ) -> impl Future<Output = anni_provider::Result<Result<String, AudioResourceReader>>>
-           + Send {
+    ) -> impl Future<Output = anni_provider::Result<Result<String, AudioResourceReader>>> + Send
+    {
         async move {
             self.get_audio(album_id, disc_id, track_id, range)
                 .await
@@ -327,12 +5046,184 @@ pub trait AnniURLProvider: AnniProvider {
         }
     }
 
+    /// Like [`get_audio`](AnniProvider::get_audio), but along with the stream info of the
+    /// track if the provider read it while streaming.
+    fn get_audio_stream(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> impl Future<Output = anni_provider::Result<StreamedAudio>> + Send {
+        async move {
+            self.get_audio(album_id, disc_id, track_id, range)
+                .await
+                .map(StreamedAudio::from)
+        }
+    }
+
+    /// Like [`get_audio_link`](Self::get_audio_link), but a link comes with the details of the
+    /// track, read from the file the link points at.
+    ///
+    /// Links may be handed out without looking at the upstream, reading the details is what
+    /// finds out about missing tracks. Providers that find the track on the way to its link
+    /// override this to read the details through the link, instead of finding the track again.
+    ///
+    /// Audio streamed by [`get_audio_link`](Self::get_audio_link) comes without stream info,
+    /// providers that stream audio with [`get_audio_stream`](Self::get_audio_stream) override
+    /// this to pass it on.
+    fn get_audio_link_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+        range: Range,
+    ) -> impl Future<Output = anni_provider::Result<LinkedAudio>> + Send {
+        async move {
+            match self
+                .get_audio_link(album_id, disc_id, track_id, range)
+                .await?
+            {
+                Ok(link) => {
+                    let details = self.get_audio_details(album_id, disc_id, track_id).await?;
+                    Ok(Ok((link, details)))
+                }
+                Err(audio) => Ok(Err(audio.into())),
+            }
+        }
+    }
+
     fn get_cover_link(
         &self,
         album_id: &str,
         disc_id: Option<NonZeroU8>,
-    ) -> impl Future<Output = anni_provider::Result<Result<String, ResourceReader>>> + Send
-    {
+    ) -> impl Future<Output = anni_provider::Result<Result<String, ResourceReader>>> + Send {
         async move { self.get_cover(album_id, disc_id).await.map(Result::Err) }
     }
+
+    /// Like [`get_cover_link`](Self::get_cover_link), but a cover served by the server comes
+    /// with the validators the upstream sent along with it.
+    ///
+    /// Providers that don't see any report none.
+    fn get_cover_validated(
+        &self,
+        album_id: &str,
+        disc_id: Option<NonZeroU8>,
+    ) -> impl Future<Output = anni_provider::Result<ValidatedCover>> + Send {
+        async move {
+            let cover = self.get_cover_link(album_id, disc_id).await?;
+            Ok(cover.map_err(|reader| (reader, Validators::default())))
+        }
+    }
+
+    /// Resolves a cover scaled down to fit in a `size`x`size` square.
+    ///
+    /// Returns `None` if the provider can't resize covers.
+    fn get_cover_thumbnail_link(
+        &self,
+        _album_id: &str,
+        _disc_id: Option<NonZeroU8>,
+        _size: u32,
+    ) -> impl Future<Output = anni_provider::Result<Option<Result<String, ResourceReader>>>> + Send
+    {
+        async { Ok(None) }
+    }
+
+    fn get_audio_details(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> impl Future<Output = anni_provider::Result<AudioDetails>> + Send {
+        async move {
+            let mut audio = self
+                .get_audio(album_id, disc_id, track_id, Range::FLAC_HEADER)
+                .await?;
+            // only the header was fetched, the size of the whole file is in the range
+            if let Some(total) = audio.range.total {
+                audio.info.size = total as usize;
+            }
+            if audio.info.extension != "flac" {
+                return Ok(AudioDetails {
+                    info: audio.info,
+                    stream: None,
+                });
+            }
+
+            let mut reader = audio.reader;
+            let mut head = [0; ID3_HEADER_SIZE];
+            reader.read_exact(&mut head).await?;
+            let mut header: ResourceReader = match id3_tag_size(&head) {
+                // the tag is skipped by its size rather than read through
+                Some(tag_size) => {
+                    let range = flac_header_after(tag_size);
+                    self.get_audio(album_id, disc_id, track_id, range)
+                        .await?
+                        .reader
+                }
+                None => Box::pin(Cursor::new(head).chain(reader)),
+            };
+            let (info, _) = read_stream_info(&mut header).await?;
+            Ok(AudioDetails {
+                info: audio.info,
+                stream: Some(StreamInfo::from(&info)),
+            })
+        }
+    }
+
+    /// Checks whether a track exists, without resolving a link to it.
+    ///
+    /// Fetches the header of the track by default, providers override it with cheaper checks.
+    fn exists(
+        &self,
+        album_id: &str,
+        disc_id: NonZeroU8,
+        track_id: NonZeroU8,
+    ) -> impl Future<Output = anni_provider::Result<bool>> + Send {
+        async move {
+            match self
+                .get_audio(album_id, disc_id, track_id, Range::FLAC_HEADER)
+                .await
+            {
+                Ok(_) => Ok(true),
+                Err(ProviderError::FileNotFound) => Ok(false),
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    /// Does the slow part of a reload, such as listing albums, while the provider can still be
+    /// read.
+    ///
+    /// Returns the albums listed once the reload is done, which the etag is computed from.
+    /// `reload` is called under the write lock of `AnnilProvider` right after, and should then
+    /// only swap in what was prepared here.
+    fn prepare_reload(
+        &self,
+    ) -> impl Future<Output = anni_provider::Result<HashSet<String>>> + Send {
+        async move {
+            let albums = self.albums().await?;
+            Ok(albums.into_iter().map(Cow::into_owned).collect())
+        }
+    }
+
+    /// Lists the discs of an album and the tracks on each.
+    ///
+    /// Returns `None` if the provider can't enumerate tracks.
+    fn list_tracks(
+        &self,
+        _album_id: &str,
+    ) -> impl Future<Output = anni_provider::Result<Option<Vec<DiscTracks>>>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Whether listing the tracks of every album is cheap enough to count them for `/stats`.
+    fn lists_tracks_cheaply(&self) -> bool {
+        false
+    }
+
+    /// Titles of an album and its tracks, if the provider knows them.
+    fn album_titles(&self, _album_id: &str) -> Option<AlbumTitles> {
+        None
+    }
 }
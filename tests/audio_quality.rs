@@ -0,0 +1,52 @@
+use annil_server::provider::{audio_quality, StreamInfo};
+
+fn stream(bits_per_sample: u8, sample_rate: u32) -> StreamInfo {
+    StreamInfo {
+        sample_rate,
+        bits_per_sample,
+        channels: 2,
+        duration_millis: 0,
+    }
+}
+
+#[test]
+fn cd_quality_flac_is_lossless() {
+    assert_eq!(audio_quality("flac", Some(&stream(16, 44100))), "lossless");
+    assert_eq!(audio_quality("flac", Some(&stream(16, 48000))), "lossless");
+}
+
+#[test]
+fn high_resolution_flac_is_lossless_hq() {
+    assert_eq!(
+        audio_quality("flac", Some(&stream(24, 44100))),
+        "lossless-hq"
+    );
+    assert_eq!(
+        audio_quality("flac", Some(&stream(16, 48001))),
+        "lossless-hq"
+    );
+    assert_eq!(
+        audio_quality("flac", Some(&stream(24, 96000))),
+        "lossless-hq"
+    );
+}
+
+#[test]
+fn flac_without_stream_info_is_lossless() {
+    assert_eq!(audio_quality("flac", None), "lossless");
+    assert_eq!(audio_quality("wav", None), "lossless");
+}
+
+#[test]
+fn lossy_formats_are_lossy() {
+    for extension in ["mp3", "opus", "aac", "m4a", "MP3"] {
+        assert_eq!(audio_quality(extension, None), "lossy", "{extension}");
+    }
+    // stream info can't make lossy audio lossless
+    assert_eq!(audio_quality("mp3", Some(&stream(24, 96000))), "lossy");
+}
+
+#[test]
+fn unknown_formats_are_unknown() {
+    assert_eq!(audio_quality("mkv", None), "unknown");
+}
@@ -0,0 +1,122 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use annil::{provider::AnnilProvider, state::AnnilKeys};
+use annil_server::{
+    make_admin_app, make_app, make_state,
+    mock::MockProvider,
+    provider::{AnniURLProvider, PathTemplate, Retry, WebdavProvider},
+    AppOptions,
+};
+use axum::{
+    body::Body,
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        Request, StatusCode,
+    },
+    Router,
+};
+use reqwest_dav::{re_exports::reqwest, Auth};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+
+pub const ALBUM_ID: &str = "4e2a1c7b-1a3f-4a4f-9d35-6a0b1f0d2c3e";
+
+/// Builds the public routes around `provider`, with the default options.
+pub async fn app<P: AnniURLProvider + Send + Sync + 'static>(provider: P) -> Router {
+    app_with(provider, AppOptions::default()).await
+}
+
+pub async fn app_with<P: AnniURLProvider + Send + Sync + 'static>(
+    provider: P,
+    options: AppOptions,
+) -> Router {
+    let provider = Arc::new(AnnilProvider::new(provider));
+    let state = Arc::new(make_state(String::from("test"), &provider, None).await);
+    make_app(provider, state, keys(), &options)
+}
+
+fn keys() -> Arc<AnnilKeys> {
+    Arc::new(AnnilKeys::new(b"sign", b"share", String::from("admin")))
+}
+
+pub fn get(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+/// Adds a user token accepted by [`app`] to `req`, signed through the admin routes.
+pub async fn authorized(mut req: Request<Body>) -> Request<Body> {
+    let provider = Arc::new(AnnilProvider::new(MockProvider::new()));
+    let state = Arc::new(make_state(String::from("test"), &provider, None).await);
+    let sign = Request::post("/admin/sign")
+        .header(AUTHORIZATION, "admin")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"user_id":"test","share":false}"#))
+        .unwrap();
+    let response = make_admin_app(provider, state, keys(), &AppOptions::default())
+        .oneshot(sign)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    req.headers_mut()
+        .insert(AUTHORIZATION, token.as_ref().try_into().unwrap());
+    req
+}
+
+/// Serves `router` on a free local port, standing in for the upstream of a provider.
+///
+/// Returns the base url of the server.
+pub async fn upstream(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{addr}")
+}
+
+/// A WebDAV provider for an upstream started with [`upstream`], with tracks at the default paths.
+pub fn webdav(host: String) -> WebdavProvider {
+    WebdavProvider::new(
+        reqwest::Client::new(),
+        host,
+        Auth::Anonymous,
+        Arc::new(PathTemplate::default()),
+        Retry::NONE,
+    )
+}
+
+/// Creates an empty directory for a library on disk, named after the test using it.
+pub fn library(test: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("annil-server-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    root
+}
+
+/// Writes a file into a library, creating the directories leading to it.
+pub fn write_file(root: &Path, path: &str, contents: &[u8]) {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+/// Builds a FLAC file holding only a STREAMINFO block of 16 bit stereo audio.
+pub fn flac(sample_rate: u32, total_samples: u64) -> Vec<u8> {
+    let mut file = b"fLaC".to_vec();
+    // last metadata block, STREAMINFO, 34 bytes long
+    file.extend_from_slice(&[0x80, 0, 0, 34]);
+    // block and frame sizes
+    file.extend_from_slice(&[0x10, 0, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+    let packed = ((sample_rate as u64) << 44) | (1 << 41) | (15 << 36) | total_samples;
+    file.extend_from_slice(&packed.to_be_bytes());
+    // md5 of the decoded audio
+    file.extend_from_slice(&[0; 16]);
+    file
+}
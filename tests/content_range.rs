@@ -0,0 +1,35 @@
+use anni_provider::Range;
+use annil_server::provider::content_range_to_range;
+
+fn parts(range: Range) -> (u64, Option<u64>, Option<u64>) {
+    (range.start, range.end, range.total)
+}
+
+#[test]
+fn satisfied_range() {
+    let range = content_range_to_range(Some("bytes 0-1023/10240"));
+    assert_eq!(parts(range), (0, Some(1023), Some(10240)));
+}
+
+#[test]
+fn unsatisfiable_range_carries_total() {
+    let range = content_range_to_range(Some("bytes */12345"));
+    assert_eq!(parts(range), (0, None, Some(12345)));
+}
+
+#[test]
+fn unknown_total() {
+    let range = content_range_to_range(Some("bytes 0-1023/*"));
+    assert_eq!(parts(range), (0, Some(1023), None));
+}
+
+#[test]
+fn empty_value_is_full_range() {
+    let range = content_range_to_range(Some(""));
+    assert_eq!(parts(range), (0, None, None));
+}
+
+#[test]
+fn missing_header_is_full_range() {
+    assert_eq!(parts(content_range_to_range(None)), (0, None, None));
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::num::NonZeroU8;
+
+use anni_provider::{AnniProvider, ProviderError, Range};
+use annil_server::provider::{AnniURLProvider, LocalFileProvider, RangeNotSatisfiable};
+use common::ALBUM_ID;
+
+#[tokio::test]
+async fn range_beyond_end_is_not_satisfiable() {
+    let root = common::library("range-beyond-end");
+    common::write_file(&root, &format!("{ALBUM_ID}/1/1.flac"), b"fLaC");
+    let provider = LocalFileProvider::new(root).with_probe_duration(false);
+
+    let range = Range {
+        start: 10,
+        end: None,
+        total: None,
+    };
+    let result = provider
+        .get_audio(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, range)
+        .await;
+    let Err(ProviderError::IOError(e)) = result else {
+        panic!("expected the range to be rejected");
+    };
+    let size = e
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<RangeNotSatisfiable>())
+        .map(|e| e.size);
+    assert_eq!(size, Some(4));
+}
+
+async fn duration(test: &str, contents: &[u8]) -> u64 {
+    let root = common::library(test);
+    common::write_file(&root, &format!("{ALBUM_ID}/1/1.flac"), contents);
+    let provider = LocalFileProvider::new(root);
+    let audio = provider
+        .get_audio(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, Range::FULL)
+        .await
+        .expect("audio");
+    audio.info.duration
+}
+
+#[tokio::test]
+async fn duration_is_rounded_to_the_nearest_second() {
+    assert_eq!(
+        duration("duration-exact", &common::flac(44100, 44100 * 180)).await,
+        180
+    );
+    assert_eq!(
+        duration("duration-down", &common::flac(48000, 48000 * 3 + 23999)).await,
+        3
+    );
+    assert_eq!(
+        duration("duration-up", &common::flac(48000, 48000 * 3 + 24000)).await,
+        4
+    );
+    // a day at 192 kHz
+    assert_eq!(
+        duration("duration-long", &common::flac(192000, 192000 * 86400)).await,
+        86400
+    );
+}
+
+#[tokio::test]
+async fn unknown_duration_is_zero() {
+    assert_eq!(
+        duration("duration-no-samples", &common::flac(44100, 0)).await,
+        0
+    );
+    assert_eq!(
+        duration("duration-no-rate", &common::flac(0, 44100)).await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn details_skip_id3_tag() {
+    let root = common::library("details-id3");
+    // ID3v2.4 header of a 300 byte tag, with the size in 7 bit bytes
+    let mut contents = b"ID3\x04\x00\x00\x00\x00\x02\x2c".to_vec();
+    contents.extend_from_slice(&[0; 300]);
+    contents.extend_from_slice(&common::flac(44100, 44100 * 10));
+    common::write_file(&root, &format!("{ALBUM_ID}/1/1.flac"), &contents);
+    let provider = LocalFileProvider::new(root);
+
+    let details = provider
+        .get_audio_details(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN)
+        .await
+        .expect("details");
+    let stream = details.stream.expect("stream info");
+    assert_eq!(stream.sample_rate, 44100);
+    assert_eq!(stream.duration_millis, 10000);
+}
+
+#[tokio::test]
+async fn details_report_whole_file_size() {
+    let root = common::library("details-size");
+    let mut contents = common::flac(44100, 44100);
+    contents.extend_from_slice(&[0; 1000]);
+    common::write_file(&root, &format!("{ALBUM_ID}/1/1.flac"), &contents);
+    let provider = LocalFileProvider::new(root);
+
+    let details = provider
+        .get_audio_details(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN)
+        .await
+        .expect("details");
+    assert_eq!(details.info.size, contents.len());
+}
+
+#[tokio::test]
+async fn tracks_are_found_by_extension() {
+    let root = common::library("extensions");
+    common::write_file(&root, &format!("{ALBUM_ID}/1/1.mp3"), b"ID3");
+    common::write_file(&root, &format!("{ALBUM_ID}/1/2.flac"), b"fLaC");
+    common::write_file(&root, &format!("{ALBUM_ID}/1/cover.jpg"), b"jpeg");
+    let provider = LocalFileProvider::new(root)
+        .with_extensions(vec![String::from("flac"), String::from("mp3")])
+        .with_probe_duration(false);
+
+    let audio = provider
+        .get_audio(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, Range::FULL)
+        .await
+        .expect("audio");
+    assert_eq!(audio.info.extension, "mp3");
+
+    let discs = provider.list_tracks(ALBUM_ID).await.unwrap().unwrap();
+    assert_eq!(discs[0].tracks.len(), 2);
+}
@@ -0,0 +1,455 @@
+mod common;
+
+use std::{
+    num::NonZeroU64,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use annil_server::{
+    mock::MockProvider,
+    provider::{ConcurrencyLimit, FallbackProvider, LocalFileProvider, UpstreamLimit},
+    AppOptions,
+};
+use axum::{
+    body::Body,
+    http::{
+        header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, ETAG, LOCATION, RANGE},
+        HeaderMap, Request, StatusCode,
+    },
+    routing::get,
+    Router,
+};
+use common::{app, authorized, get, ALBUM_ID};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn audio_redirects_to_link() {
+    let provider = MockProvider::new()
+        .with_track(ALBUM_ID, 1, 2, &b"fLaC"[..])
+        .with_links("https://cdn.example.com");
+    let response = app(provider)
+        .await
+        .oneshot(authorized(get(&format!("/{ALBUM_ID}/1/2"))).await)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.headers()[LOCATION],
+        format!("https://cdn.example.com/{ALBUM_ID}/1/2").as_str()
+    );
+    assert_eq!(response.headers()["X-Origin-Size"], "4");
+}
+
+#[tokio::test]
+async fn audio_streams_requested_range() {
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, &b"fLaC"[..]);
+    let request = Request::get(format!("/{ALBUM_ID}/1/1"))
+        .header(RANGE, "bytes=1-2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(provider)
+        .await
+        .oneshot(authorized(request).await)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes 1-2/4");
+    assert_eq!(response.headers()[CONTENT_LENGTH], "2");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], b"La");
+}
+
+#[tokio::test]
+async fn head_reports_size_without_body() {
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, vec![0; 2000]);
+    let request = Request::head(format!("/{ALBUM_ID}/1/1"))
+        .body(Body::empty())
+        .unwrap();
+    let response = app(provider)
+        .await
+        .oneshot(authorized(request).await)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_LENGTH], "2000");
+    assert_eq!(response.headers()["X-Origin-Size"], "2000");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(body.is_empty());
+}
+
+#[tokio::test]
+async fn streamed_audio_reports_the_stream_info_head_does() {
+    let root = common::library("streamed-stream-info");
+    common::write_file(
+        &root,
+        &format!("{ALBUM_ID}/1/1.flac"),
+        &common::flac(96000, 96000 * 60),
+    );
+    let app = app(LocalFileProvider::new(root)).await;
+
+    let head = Request::head(format!("/{ALBUM_ID}/1/1"))
+        .body(Body::empty())
+        .unwrap();
+    let head = app.clone().oneshot(authorized(head).await).await.unwrap();
+    // the range leaves out the header, which is read from the file anyway
+    let request = Request::get(format!("/{ALBUM_ID}/1/1"))
+        .header(RANGE, "bytes=50-")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(authorized(request).await).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()["X-Audio-Quality"], "lossless-hq");
+    for header in [
+        "X-Audio-Quality",
+        "X-Duration-Millis",
+        "X-Sample-Rate",
+        "X-Bit-Depth",
+        "X-Channels",
+    ] {
+        assert_eq!(
+            response.headers()[header],
+            head.headers()[header],
+            "{header}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn range_beyond_end_is_not_satisfiable() {
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, &b"fLaC"[..]);
+    let response = ranged(&app(provider).await, "bytes=10-").await;
+
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes */4");
+}
+
+#[tokio::test]
+async fn missing_audio_is_not_found() {
+    let provider = MockProvider::new().with_links("https://cdn.example.com");
+    let response = app(provider)
+        .await
+        .oneshot(authorized(get(&format!("/{ALBUM_ID}/1/1"))).await)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn failing_upstream_is_bad_gateway() {
+    let provider = MockProvider::new()
+        .with_track(ALBUM_ID, 1, 1, &b"fLaC"[..])
+        .with_failure();
+    let response = app(provider)
+        .await
+        .oneshot(authorized(get(&format!("/{ALBUM_ID}/1/1"))).await)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn albums_only_the_fallback_has_are_served_from_it() {
+    let secondary = MockProvider::new().with_track(ALBUM_ID, 1, 1, &b"fLaC"[..]);
+    let provider = FallbackProvider::new(MockProvider::new(), Some(secondary));
+    let response = app(provider)
+        .await
+        .oneshot(authorized(get(&format!("/{ALBUM_ID}/1/1"))).await)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn busy_upstream_is_unavailable() {
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, &b"fLaC"[..]);
+    let limit = UpstreamLimit::new(1, Duration::ZERO);
+    let app = app(ConcurrencyLimit::new(provider, limit)).await;
+
+    // the streamed body holds the only permit until it is dropped
+    let streaming = app
+        .clone()
+        .oneshot(authorized(get(&format!("/{ALBUM_ID}/1/1"))).await)
+        .await
+        .unwrap();
+    assert_eq!(streaming.status(), StatusCode::OK);
+    let response = app
+        .oneshot(authorized(get(&format!("/{ALBUM_ID}/1/1"))).await)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()["Retry-After"], "1");
+}
+
+#[tokio::test]
+async fn range_reaches_provider_intact() {
+    let received = Arc::new(Mutex::new(None));
+    let router = Router::new().route(
+        &format!("/{ALBUM_ID}/1/1"),
+        get({
+            let received = received.clone();
+            move |headers: HeaderMap| async move {
+                *received.lock().unwrap() = headers
+                    .get(RANGE)
+                    .map(|range| range.to_str().unwrap().to_owned());
+                (
+                    StatusCode::PARTIAL_CONTENT,
+                    [(CONTENT_RANGE, "bytes 1000-1999/2000")],
+                    vec![0; 1000],
+                )
+            }
+        }),
+    );
+    let provider = common::webdav(common::upstream(router).await).with_probe_duration(false);
+    let options = AppOptions {
+        proxy_audio: true,
+        ..Default::default()
+    };
+    let request = Request::get(format!("/{ALBUM_ID}/1/1"))
+        .header(RANGE, "bytes=1000-")
+        .body(Body::empty())
+        .unwrap();
+    let response = common::app_with(provider, options)
+        .await
+        .oneshot(authorized(request).await)
+        .await
+        .unwrap();
+
+    assert_eq!(received.lock().unwrap().as_deref(), Some("bytes=1000-"));
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes 1000-1999/2000");
+}
+
+#[tokio::test]
+async fn audio_without_token_is_unauthorized() {
+    let provider = MockProvider::new()
+        .with_track(ALBUM_ID, 1, 1, &b"fLaC"[..])
+        .with_links("https://cdn.example.com");
+    let app = app(provider).await;
+
+    for request in [
+        get(&format!("/{ALBUM_ID}/1/1")),
+        Request::head(format!("/{ALBUM_ID}/1/1"))
+            .body(Body::empty())
+            .unwrap(),
+        get(&format!("/{ALBUM_ID}/1/1/meta")),
+        get(&format!("/{ALBUM_ID}")),
+        Request::get(format!("/{ALBUM_ID}/1/1"))
+            .header(AUTHORIZATION, "not a token")
+            .body(Body::empty())
+            .unwrap(),
+    ] {
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[tokio::test]
+async fn ignored_range_is_answered_with_whole_file() {
+    let router = Router::new().route(&format!("/{ALBUM_ID}/1/1"), get(|| async { vec![0; 2000] }));
+    let provider = common::webdav(common::upstream(router).await).with_probe_duration(false);
+    let options = AppOptions {
+        proxy_audio: true,
+        ..Default::default()
+    };
+    let request = Request::get(format!("/{ALBUM_ID}/1/1"))
+        .header(RANGE, "bytes=1000-")
+        .body(Body::empty())
+        .unwrap();
+    let response = common::app_with(provider, options)
+        .await
+        .oneshot(authorized(request).await)
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(CONTENT_RANGE));
+    assert_eq!(response.headers()[CONTENT_LENGTH], "2000");
+}
+
+#[tokio::test]
+async fn multiple_ranges_are_served_in_full() {
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, &b"fLaC"[..]);
+    let app = app(provider).await;
+
+    for range in ["bytes=0-0,2-3", "bytes=0-1, 1-2", "bytes=-1,0-"] {
+        let request = Request::get(format!("/{ALBUM_ID}/1/1"))
+            .header(RANGE, range)
+            .body(Body::empty())
+            .unwrap();
+        let response = app
+            .clone()
+            .oneshot(authorized(request).await)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK, "{range}");
+        assert!(!response.headers().contains_key(CONTENT_RANGE), "{range}");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"fLaC", "{range}");
+    }
+}
+
+/// Requests track 1 of disc 1 with a `Range` header.
+async fn ranged(app: &Router, range: &str) -> axum::response::Response {
+    let request = Request::get(format!("/{ALBUM_ID}/1/1"))
+        .header(RANGE, range)
+        .body(Body::empty())
+        .unwrap();
+    app.clone()
+        .oneshot(authorized(request).await)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn open_and_suffix_ranges_are_partial() {
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, vec![0; 2000]);
+    let app = app(provider).await;
+
+    for (range, content_range) in [
+        ("bytes=500-", "bytes 500-1999/2000"),
+        ("bytes=-500", "bytes 1500-1999/2000"),
+    ] {
+        let response = ranged(&app, range).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT, "{range}");
+        assert_eq!(response.headers()[CONTENT_RANGE], content_range, "{range}");
+    }
+}
+
+#[tokio::test]
+async fn invalid_ranges_are_ignored() {
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, vec![0; 2000]);
+    let app = app(provider).await;
+
+    for range in [
+        "bytes=abc-def",
+        "bytes=5-1",
+        "bytes=+1-2",
+        "items=0-1",
+        "bytes=1",
+    ] {
+        let response = ranged(&app, range).await;
+        assert_eq!(response.status(), StatusCode::OK, "{range}");
+        assert!(!response.headers().contains_key(CONTENT_RANGE), "{range}");
+    }
+}
+
+#[tokio::test]
+async fn empty_suffix_range_is_not_satisfiable() {
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, vec![0; 2000]);
+    let response = ranged(&app(provider).await, "bytes=-0").await;
+
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes */2000");
+}
+
+#[tokio::test]
+async fn suffix_range_round_trips() {
+    let audio: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+    let provider = MockProvider::new().with_track(ALBUM_ID, 1, 1, audio.clone());
+    let response = ranged(&app(provider).await, "bytes=-500").await;
+
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes 1500-1999/2000");
+    assert_eq!(response.headers()[CONTENT_LENGTH], "500");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], &audio[1500..]);
+}
+
+#[tokio::test]
+async fn oversized_audio_is_refused_whatever_the_range() {
+    let options = || AppOptions {
+        max_audio_bytes: Some(1000),
+        ..Default::default()
+    };
+    let streamed = MockProvider::new().with_track(ALBUM_ID, 1, 1, vec![0; 2000]);
+    let linked = MockProvider::new()
+        .with_track(ALBUM_ID, 1, 1, vec![0; 2000])
+        .with_links("https://cdn.example.com");
+
+    for app in [
+        common::app_with(streamed, options()).await,
+        common::app_with(linked, options()).await,
+    ] {
+        let response = ranged(&app, "bytes=0-99").await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
+
+#[tokio::test]
+async fn per_request_limit_only_cuts_ranges() {
+    let options = AppOptions {
+        max_bytes_per_request: NonZeroU64::new(500),
+        ..Default::default()
+    };
+    let provider = MockProvider::new()
+        .with_track(ALBUM_ID, 1, 1, vec![0; 2000])
+        .with_track(ALBUM_ID, 1, 2, vec![0; 100]);
+    let app = common::app_with(provider, options).await;
+
+    let response = ranged(&app, "bytes=1000-").await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[CONTENT_RANGE], "bytes 1000-1499/2000");
+    assert_eq!(response.headers()[CONTENT_LENGTH], "500");
+
+    // requests without a range take the whole file or nothing
+    for (track, status) in [(1, StatusCode::PAYLOAD_TOO_LARGE), (2, StatusCode::OK)] {
+        let request = get(&format!("/{ALBUM_ID}/1/{track}"));
+        let response = app
+            .clone()
+            .oneshot(authorized(request).await)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), status, "track {track}");
+    }
+}
+
+#[tokio::test]
+async fn albums_are_paged_with_their_own_etags() {
+    let ids = [
+        "00000000-0000-4000-8000-000000000001",
+        "00000000-0000-4000-8000-000000000002",
+        "00000000-0000-4000-8000-000000000003",
+    ];
+    let provider = ids.iter().fold(MockProvider::new(), |provider, id| {
+        provider.with_track(id, 1, 1, &b"fLaC"[..])
+    });
+    let app = app(provider).await;
+
+    let mut etags = Vec::new();
+    for (offset, expected) in [(0, ids[0]), (1, ids[1])] {
+        let request = get(&format!("/albums?offset={offset}&limit=1"));
+        let response = app
+            .clone()
+            .oneshot(authorized(request).await)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        etags.push(response.headers()[ETAG].clone());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page["albums"], serde_json::json!([expected]));
+        assert_eq!(page["total"], 3);
+        assert_eq!(page["next"], offset + 1);
+    }
+    assert_ne!(etags[0], etags[1]);
+}
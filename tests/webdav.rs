@@ -0,0 +1,278 @@
+mod common;
+
+use std::{
+    num::NonZeroU8,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anni_provider::{AnniProvider, ProviderError, Range};
+use annil_server::provider::{
+    AnniURLProvider, PathTemplate, RangeNotSatisfiable, Retry, WebdavProvider,
+};
+use axum::{
+    body::{Body, Bytes},
+    http::{
+        header::{CONTENT_RANGE, ETAG, LAST_MODIFIED},
+        StatusCode,
+    },
+    routing::get,
+    Router,
+};
+use common::ALBUM_ID;
+use futures_util::StreamExt;
+use reqwest_dav::{re_exports::reqwest, Auth};
+use tokio::io::AsyncReadExt;
+
+#[tokio::test]
+async fn cover_is_read_from_disc_dir() {
+    let router = Router::new().route(
+        &format!("/{ALBUM_ID}/1/cover.jpg"),
+        get(|| async { "cover" }),
+    );
+    let provider = common::webdav(common::upstream(router).await);
+
+    let mut cover = Vec::new();
+    provider
+        .get_cover(ALBUM_ID, None)
+        .await
+        .unwrap()
+        .read_to_end(&mut cover)
+        .await
+        .unwrap();
+    assert_eq!(cover, b"cover");
+}
+
+#[tokio::test]
+async fn cover_carries_upstream_validators() {
+    let router = Router::new().route(
+        &format!("/{ALBUM_ID}/1/cover.jpg"),
+        get(|| async {
+            (
+                [
+                    (ETAG, "\"upstream\""),
+                    (LAST_MODIFIED, "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ],
+                "cover",
+            )
+        }),
+    );
+    let provider = common::webdav(common::upstream(router).await);
+
+    let Err((_, validators)) = provider.get_cover_validated(ALBUM_ID, None).await.unwrap() else {
+        panic!("covers are served by the server");
+    };
+    assert_eq!(validators.etag.as_deref(), Some("\"upstream\""));
+    assert_eq!(
+        validators.last_modified,
+        Some(UNIX_EPOCH + Duration::from_secs(784111777))
+    );
+}
+
+#[tokio::test]
+async fn missing_cover_is_not_found() {
+    let provider = common::webdav(common::upstream(Router::new()).await);
+
+    let result = provider.get_cover(ALBUM_ID, None).await;
+    assert!(matches!(result, Err(ProviderError::FileNotFound)));
+}
+
+/// A body of unknown length, which is sent chunked and without `Content-Length`.
+fn chunked(body: &'static [u8]) -> Body {
+    Body::from_stream(futures_util::stream::iter([Ok::<_, std::io::Error>(
+        Bytes::from_static(body),
+    )]))
+}
+
+#[tokio::test]
+async fn chunked_audio_takes_size_from_content_range() {
+    let router = Router::new().route(
+        &format!("/{ALBUM_ID}/1/1"),
+        get(|| async {
+            (
+                StatusCode::PARTIAL_CONTENT,
+                [(CONTENT_RANGE, "bytes 0-3/4")],
+                chunked(b"fLaC"),
+            )
+        }),
+    );
+    let provider = common::webdav(common::upstream(router).await).with_probe_duration(false);
+
+    let mut audio = provider
+        .get_audio(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, Range::FULL)
+        .await
+        .unwrap();
+    assert_eq!(audio.info.size, 4);
+    let mut body = Vec::new();
+    audio.reader.read_to_end(&mut body).await.unwrap();
+    assert_eq!(body, b"fLaC");
+}
+
+#[tokio::test]
+async fn chunked_audio_without_size_is_an_error() {
+    let router = Router::new().route(
+        &format!("/{ALBUM_ID}/1/1"),
+        get(|| async { chunked(b"fLaC") }),
+    );
+    let provider = common::webdav(common::upstream(router).await).with_probe_duration(false);
+
+    let result = provider
+        .get_audio(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, Range::FULL)
+        .await;
+    assert!(matches!(result, Err(ProviderError::GeneralError)));
+}
+
+#[tokio::test]
+async fn audio_error_is_not_streamed() {
+    let router = Router::new().route(
+        &format!("/{ALBUM_ID}/1/1"),
+        get(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "oops") }),
+    );
+    let provider = common::webdav(common::upstream(router).await).with_probe_duration(false);
+
+    let result = provider
+        .get_audio(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, Range::FULL)
+        .await;
+    let Err(ProviderError::RequestError(e)) = result else {
+        panic!("expected the error response to be reported");
+    };
+    assert_eq!(e.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+}
+
+#[tokio::test]
+async fn unsatisfiable_audio_range_carries_size() {
+    let router = Router::new().route(
+        &format!("/{ALBUM_ID}/1/1"),
+        get(|| async {
+            (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, "bytes */4")],
+            )
+        }),
+    );
+    let provider = common::webdav(common::upstream(router).await).with_probe_duration(false);
+
+    let range = Range {
+        start: 10,
+        end: None,
+        total: None,
+    };
+    let result = provider
+        .get_audio(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, range)
+        .await;
+    let Err(ProviderError::IOError(e)) = result else {
+        panic!("expected the range to be rejected");
+    };
+    let size = e
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<RangeNotSatisfiable>())
+        .map(|e| e.size);
+    assert_eq!(size, Some(4));
+}
+
+fn with_basic_auth(host: String) -> WebdavProvider {
+    WebdavProvider::new(
+        reqwest::Client::new(),
+        host,
+        Auth::Basic(String::from("user"), String::from("secret")),
+        Arc::new(PathTemplate::default()),
+        Retry::NONE,
+    )
+    .with_probe_duration(false)
+}
+
+#[tokio::test]
+async fn basic_auth_audio_is_streamed() {
+    let router = Router::new().route(&format!("/{ALBUM_ID}/1/1"), get(|| async { "fLaC" }));
+    let provider = with_basic_auth(common::upstream(router).await);
+
+    let link = provider
+        .get_audio_link(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, Range::FULL)
+        .await
+        .unwrap();
+    assert!(link.is_err(), "credentials must not be handed out in links");
+}
+
+#[tokio::test]
+async fn basic_auth_links_are_opt_in() {
+    let host = common::upstream(Router::new()).await;
+    let provider = with_basic_auth(host.clone()).with_credentials_in_links(true);
+
+    let link = provider
+        .get_audio_link(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, Range::FULL)
+        .await
+        .unwrap();
+    let expected = host.replacen("http://", "http://user:secret@", 1);
+    assert_eq!(link.ok(), Some(format!("{expected}/{ALBUM_ID}/1/1")));
+}
+
+/// Counts the bytes of a body the upstream has produced, to tell how far ahead of the reader
+/// the provider pulled it.
+fn counted(header: Vec<u8>, chunks: usize, produced: Arc<AtomicUsize>) -> Body {
+    let header = futures_util::stream::once(async move { Bytes::from(header) });
+    let chunks = futures_util::stream::repeat(Bytes::from_static(&[0; CHUNK_SIZE])).take(chunks);
+    Body::from_stream(header.chain(chunks).map(move |chunk| {
+        produced.fetch_add(chunk.len(), Ordering::Relaxed);
+        Ok::<_, std::io::Error>(chunk)
+    }))
+}
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[tokio::test]
+async fn audio_is_streamed_without_buffering() {
+    let header = common::flac(44100, 44100);
+    let chunks = 1024;
+    let size = header.len() + chunks * CHUNK_SIZE;
+    let produced = Arc::new(AtomicUsize::new(0));
+    let router = Router::new().route(
+        &format!("/{ALBUM_ID}/1/1"),
+        get({
+            let produced = produced.clone();
+            move || {
+                let body = counted(header.clone(), chunks, produced.clone());
+                async move {
+                    (
+                        StatusCode::PARTIAL_CONTENT,
+                        [(CONTENT_RANGE, format!("bytes 0-{}/{size}", size - 1))],
+                        body,
+                    )
+                }
+            }
+        }),
+    );
+    let provider = common::webdav(common::upstream(router).await);
+
+    let mut audio = provider
+        .get_audio(ALBUM_ID, NonZeroU8::MIN, NonZeroU8::MIN, Range::FULL)
+        .await
+        .unwrap();
+    assert_eq!(audio.info.duration, 1);
+    // give the upstream time to fill whatever the provider would buffer
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // socket buffers hold a few MiB at most, far from the 64 MiB of the body
+    let ahead = produced.load(Ordering::Relaxed);
+    assert!(
+        ahead < 16 * 1024 * 1024,
+        "{ahead} bytes pulled before reading"
+    );
+
+    let mut read = 0;
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = audio.reader.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        read += n;
+        let ahead = produced.load(Ordering::Relaxed) - read;
+        assert!(
+            ahead < 16 * 1024 * 1024,
+            "{ahead} bytes pulled ahead of the reader"
+        );
+    }
+    assert_eq!(read, size);
+}